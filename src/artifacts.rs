@@ -0,0 +1,84 @@
+//! The JSON output of `cargo build --message-format=json` and `cargo metadata`.
+
+use rustc_serialize::json::Json;
+
+/// An executable that cargo built.
+#[derive(Debug, Clone)]
+pub struct Artifact {
+    pub name: String,
+    /// `bin`, `example` or `test`.
+    pub kind: String,
+    pub path: String,
+}
+
+pub enum Message {
+    Artifact(Artifact),
+    /// A compiler warning or error, already formatted by rustc.
+    Diagnostic(String),
+    Other,
+}
+
+pub fn parse(line: &str) -> Result<Message, String> {
+    let json = try!(Json::from_str(line).map_err(|e| format!("{}", e)));
+    match json.find("reason").and_then(|r| r.as_string()) {
+        Some("compiler-artifact") => {}
+        Some("compiler-message") => {
+            return Ok(json.find_path(&["message", "rendered"])
+                .and_then(|r| r.as_string())
+                .map_or(Message::Other, |r| Message::Diagnostic(r.into())));
+        }
+        _ => return Ok(Message::Other),
+    }
+
+    let name = json.find_path(&["target", "name"]).and_then(|n| n.as_string());
+    let kind = json.find_path(&["target", "kind"]).and_then(|k| k.as_array())
+        .and_then(|k| k.get(0)).and_then(|k| k.as_string());
+    // Older cargos do not report "executable", the first file name is the executable then.
+    let path = json.find("executable").and_then(|e| e.as_string())
+        .or_else(|| json.find("filenames").and_then(|f| f.as_array())
+                 .and_then(|f| f.get(0)).and_then(|f| f.as_string()));
+    match (name, kind, path) {
+        (Some(name), Some(kind), Some(path)) if kind == "bin" || kind == "example" => {
+            Ok(Message::Artifact(Artifact { name: name.into(), kind: kind.into(), path: path.into() }))
+        }
+        _ => Ok(Message::Other),
+    }
+}
+
+/// Picks the firmware among the built executables. That is the one of the
+/// `wanted` kind and name if given, else the one called `preferred`, else the
+/// only one there is. Returns `None` if that is not possible.
+pub fn select(artifacts: &[Artifact], wanted: Option<(&str, &str)>, preferred: &str)
+              -> Option<Artifact> {
+    if let Some((kind, name)) = wanted {
+        return artifacts.iter().find(|a| a.kind == kind && a.name == name).cloned();
+    }
+    if let Some(artifact) = artifacts.iter().find(|a| a.name == preferred) {
+        return Some(artifact.clone());
+    }
+    if artifacts.len() == 1 {
+        Some(artifacts[0].clone())
+    } else {
+        None
+    }
+}
+
+/// The `(kind, name)` of all binaries and examples in `cargo metadata --no-deps` output.
+pub fn targets(metadata: &str) -> Result<Vec<(String, String)>, String> {
+    let json = try!(Json::from_str(metadata).map_err(|e| format!("{}", e)));
+    let mut targets = Vec::new();
+    for package in json.find("packages").and_then(|p| p.as_array()).unwrap_or(&Vec::new()) {
+        for target in package.find("targets").and_then(|t| t.as_array()).unwrap_or(&Vec::new()) {
+            let name = target.find("name").and_then(|n| n.as_string());
+            let kind = target.find("kind").and_then(|k| k.as_array())
+                .and_then(|k| k.get(0)).and_then(|k| k.as_string());
+            match (kind, name) {
+                (Some(kind), Some(name)) if kind == "bin" || kind == "example" => {
+                    targets.push((kind.to_string(), name.to_string()));
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(targets)
+}
@@ -0,0 +1,127 @@
+//! The Teensy boards cargo-teensy knows how to build for and upload to.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Board {
+    /// The name used with `--board`.
+    pub name: &'static str,
+    pub description: &'static str,
+    pub mcu: &'static str,
+    /// The `cpu` of the generated target specification.
+    pub cpu: &'static str,
+    pub target: &'static str,
+    pub flash_size: u32,
+    /// The address of the first byte of RAM.
+    pub ram_start: u32,
+    pub ram_size: u32,
+    /// The MCU name used by the HalfKay uploader.
+    pub loader_mcu: &'static str,
+    /// The cargo feature of the generated project that selects the HAL for this MCU.
+    pub feature: &'static str,
+    /// The Arduino pin number of the on-board LED.
+    pub led_pin: u32,
+    /// The GPIO port (`A` to `E`) and bit the LED is connected to.
+    pub led_port: &'static str,
+    pub led_bit: u32,
+}
+
+pub const DEFAULT_BOARD: &'static str = "teensy31";
+
+pub const BOARDS: &'static [Board] = &[
+    Board {
+        name: "teensy30",
+        description: "Teensy 3.0",
+        mcu: "MK20DX128",
+        cpu: "cortex-m4",
+        target: "thumbv7em-none-eabi",
+        flash_size: 128 * 1024,
+        ram_start: 0x1FFF_E000,
+        ram_size: 16 * 1024,
+        loader_mcu: "mk20dx128",
+        feature: "mcu_k20",
+        led_pin: 13,
+        led_port: "C",
+        led_bit: 5,
+    },
+    Board {
+        name: "teensy31",
+        description: "Teensy 3.1",
+        mcu: "MK20DX256",
+        cpu: "cortex-m4",
+        target: "thumbv7em-none-eabi",
+        flash_size: 256 * 1024,
+        ram_start: 0x1FFF_8000,
+        ram_size: 64 * 1024,
+        loader_mcu: "mk20dx256",
+        feature: "mcu_k20",
+        led_pin: 13,
+        led_port: "C",
+        led_bit: 5,
+    },
+    Board {
+        name: "teensy32",
+        description: "Teensy 3.2",
+        mcu: "MK20DX256",
+        cpu: "cortex-m4",
+        target: "thumbv7em-none-eabi",
+        flash_size: 256 * 1024,
+        ram_start: 0x1FFF_8000,
+        ram_size: 64 * 1024,
+        loader_mcu: "mk20dx256",
+        feature: "mcu_k20",
+        led_pin: 13,
+        led_port: "C",
+        led_bit: 5,
+    },
+    Board {
+        name: "teensy35",
+        description: "Teensy 3.5",
+        mcu: "MK64FX512",
+        cpu: "cortex-m4",
+        target: "thumbv7em-none-eabi",
+        flash_size: 512 * 1024,
+        ram_start: 0x1FFF_0000,
+        ram_size: 192 * 1024,
+        loader_mcu: "mk64fx512",
+        feature: "mcu_k64",
+        led_pin: 13,
+        led_port: "C",
+        led_bit: 5,
+    },
+    Board {
+        name: "teensy36",
+        description: "Teensy 3.6",
+        mcu: "MK66FX1M0",
+        cpu: "cortex-m4",
+        target: "thumbv7em-none-eabi",
+        flash_size: 1024 * 1024,
+        ram_start: 0x1FFF_0000,
+        ram_size: 256 * 1024,
+        loader_mcu: "mk66fx1m0",
+        feature: "mcu_k66",
+        led_pin: 13,
+        led_port: "C",
+        led_bit: 5,
+    },
+    Board {
+        name: "teensylc",
+        description: "Teensy LC",
+        mcu: "MKL26Z64",
+        cpu: "cortex-m0plus",
+        target: "thumbv6m-none-eabi",
+        flash_size: 62 * 1024,
+        ram_start: 0x1FFF_F800,
+        ram_size: 8 * 1024,
+        loader_mcu: "mkl26z64",
+        feature: "mcu_kl26",
+        led_pin: 13,
+        led_port: "C",
+        led_bit: 5,
+    },
+];
+
+pub fn find(name: &str) -> Result<&'static Board, String> {
+    let wanted = name.to_lowercase().replace(".", "").replace(" ", "");
+    BOARDS.iter().find(|b| b.name == wanted)
+        .ok_or_else(|| format!("Unknown board '{}'. Available boards: {}", name,
+                               BOARDS.iter().map(|b| b.name).collect::<Vec<_>>().join(", ")))
+}
@@ -0,0 +1,79 @@
+//! The required rust version, remembered between runs so that not every
+//! `cargo teensy new` needs the network.
+//!
+//! The cache file holds the source the version was read from and the version,
+//! one per line. It is fresh for `TTL_SECS` after it was written.
+
+use std::env;
+use std::fs::{self, DirBuilder, File};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use error::Error;
+
+const TTL_SECS: u64 = 24 * 60 * 60;
+
+pub struct Entry {
+    pub version: String,
+    /// Whether the entry is younger than the TTL.
+    pub fresh: bool,
+}
+
+/// The per-user cache directory of the platform.
+fn cache_dir() -> Option<PathBuf> {
+    if cfg!(target_os = "macos") {
+        return env::home_dir().map(|home| home.join("Library").join("Caches"));
+    }
+    if cfg!(windows) {
+        return env::var_os("LOCALAPPDATA").map(PathBuf::from);
+    }
+    match env::var_os("XDG_CACHE_HOME") {
+        Some(ref dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => env::home_dir().map(|home| home.join(".cache")),
+    }
+}
+
+fn path() -> Option<PathBuf> {
+    cache_dir().map(|dir| dir.join("cargo-teensy").join("rust-version"))
+}
+
+/// The cached version read from `source`, if there is one.
+pub fn read(source: &str) -> Option<Entry> {
+    let path = match path() {
+        Some(path) => path,
+        None => return None,
+    };
+    let mut s = String::new();
+    if File::open(&path).and_then(|mut f| f.read_to_string(&mut s)).is_err() {
+        return None;
+    }
+    let mut lines = s.lines();
+    if lines.next() != Some(source) {
+        return None;
+    }
+    let version = match lines.next() {
+        Some(version) if !version.is_empty() => version.to_string(),
+        _ => return None,
+    };
+    let age = fs::metadata(&path).and_then(|m| m.modified()).ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+    Some(Entry {
+        version: version,
+        fresh: age.map_or(false, |age| age < Duration::from_secs(TTL_SECS)),
+    })
+}
+
+pub fn write(source: &str, version: &str) -> Result<(), Error> {
+    let path = match path() {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    if let Some(dir) = path.parent() {
+        try!(DirBuilder::new().recursive(true).create(dir)
+             .map_err(|ioerr| Error::io(&dir.to_string_lossy(), ioerr)));
+    }
+    let name = path.to_string_lossy().into_owned();
+    let mut f = try!(File::create(&path).map_err(|ioerr| Error::io(&name, ioerr)));
+    write!(f, "{}\n{}\n", source, version).map_err(|ioerr| Error::io(&name, ioerr))
+}
@@ -0,0 +1,147 @@
+//! Per-project settings from the `[package.metadata.teensy]` table of Cargo.toml,
+//! and the toolchain the project pins in rust-toolchain.toml.
+//!
+//! ```toml
+//! [package.metadata.teensy]
+//! board = "teensy32"
+//! reboot = "soft"          # or "hard", like -s / -r
+//! no-reboot = false        # like -n
+//! features = ["logging"]
+//! build-args = ["--locked"]
+//! profile = "release"      # "dev" or any custom profile, like --debug / --profile
+//! flash-budget = 90        # percent, checked by `cargo teensy size`
+//! ram-budget = 75
+//!
+//! [package.metadata.teensy.tools]
+//! cargo = "/opt/rust/bin/cargo"
+//! linker = "arm-none-eabi-gcc"
+//! ```
+
+use toml;
+
+use halfkay::Reboot;
+
+const TABLE: &'static str = "package.metadata.teensy";
+const TOOLCHAIN_TABLE: &'static str = "toolchain";
+
+#[derive(Debug, Default)]
+pub struct Config {
+    pub board: Option<String>,
+    pub reboot: Option<Reboot>,
+    pub no_reboot: Option<bool>,
+    pub features: Vec<String>,
+    pub build_args: Vec<String>,
+    pub profile: Option<String>,
+    pub flash_budget: Option<u32>,
+    pub ram_budget: Option<u32>,
+    pub cargo: Option<String>,
+    pub linker: Option<String>,
+    /// The features of the `[features]` table, not part of the settings.
+    pub declared_features: Vec<String>,
+}
+
+/// The `[toolchain]` table of rust-toolchain.toml.
+#[derive(Debug, Default)]
+pub struct ToolchainFile {
+    pub channel: Option<String>,
+    pub components: Vec<String>,
+}
+
+fn string(table: &toml::Table, key: &str, path: &str) -> Result<Option<String>, String> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(value) => value.as_str().map(|s| Some(s.into()))
+            .ok_or(format!("{}.{} must be a string", path, key)),
+    }
+}
+
+fn boolean(table: &toml::Table, key: &str, path: &str) -> Result<Option<bool>, String> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(value) => value.as_bool().map(Some)
+            .ok_or(format!("{}.{} must be true or false", path, key)),
+    }
+}
+
+fn percent(table: &toml::Table, key: &str, path: &str) -> Result<Option<u32>, String> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(value) => match value.as_integer() {
+            Some(i) if i >= 0 && i <= 100 => Ok(Some(i as u32)),
+            _ => Err(format!("{}.{} must be a percentage between 0 and 100", path, key)),
+        },
+    }
+}
+
+fn strings(table: &toml::Table, key: &str, path: &str) -> Result<Vec<String>, String> {
+    let error = format!("{}.{} must be a list of strings", path, key);
+    match table.get(key) {
+        None => Ok(Vec::new()),
+        Some(value) => {
+            let items = try!(value.as_slice().ok_or(error.clone()));
+            items.iter().map(|i| i.as_str().map(|s| s.into()).ok_or(error.clone())).collect()
+        }
+    }
+}
+
+impl Config {
+    pub fn from_manifest(manifest: &toml::Table) -> Result<Config, String> {
+        let declared_features = match manifest.get("features") {
+            None => Vec::new(),
+            Some(value) => try!(value.as_table().ok_or("features must be a table".to_string()))
+                .keys().cloned().collect(),
+        };
+        let root = toml::Value::Table(manifest.clone());
+        let table = match root.lookup(TABLE) {
+            None => return Ok(Config { declared_features: declared_features, ..Config::default() }),
+            Some(value) => try!(value.as_table().ok_or(format!("{} must be a table", TABLE))),
+        };
+
+        let reboot = match try!(string(table, "reboot", TABLE)) {
+            None => None,
+            Some(ref mode) if mode == "soft" => Some(Reboot::Soft),
+            Some(ref mode) if mode == "hard" => Some(Reboot::Hard),
+            Some(ref mode) if mode == "none" => Some(Reboot::None),
+            Some(mode) => return Err(format!("{}.reboot must be \"soft\", \"hard\" or \"none\", \
+                                              not \"{}\"", TABLE, mode)),
+        };
+
+        let tools_path = format!("{}.tools", TABLE);
+        let empty = toml::Table::new();
+        let tools = match table.get("tools") {
+            None => &empty,
+            Some(value) => try!(value.as_table().ok_or(format!("{} must be a table", tools_path))),
+        };
+
+        Ok(Config {
+            board: try!(string(table, "board", TABLE)),
+            reboot: reboot,
+            no_reboot: try!(boolean(table, "no-reboot", TABLE)),
+            features: try!(strings(table, "features", TABLE)),
+            build_args: try!(strings(table, "build-args", TABLE)),
+            profile: try!(string(table, "profile", TABLE)),
+            flash_budget: try!(percent(table, "flash-budget", TABLE)),
+            ram_budget: try!(percent(table, "ram-budget", TABLE)),
+            cargo: try!(string(tools, "cargo", &tools_path)),
+            linker: try!(string(tools, "linker", &tools_path)),
+            declared_features: declared_features,
+        })
+    }
+}
+
+impl ToolchainFile {
+    pub fn parse(s: &str) -> Result<ToolchainFile, String> {
+        let mut parser = toml::Parser::new(s);
+        let root = try!(parser.parse().ok_or("not valid TOML".to_string()));
+        let table = match root.get(TOOLCHAIN_TABLE) {
+            None => return Ok(ToolchainFile::default()),
+            Some(value) => {
+                try!(value.as_table().ok_or(format!("{} must be a table", TOOLCHAIN_TABLE)))
+            }
+        };
+        Ok(ToolchainFile {
+            channel: try!(string(table, "channel", TOOLCHAIN_TABLE)),
+            components: try!(strings(table, "components", TOOLCHAIN_TABLE)),
+        })
+    }
+}
@@ -0,0 +1,155 @@
+//! Line based unified diffs, to show what `--dry-run` would change.
+
+/// Lines of context around each change.
+const CONTEXT: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Line<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+impl<'a> Line<'a> {
+    fn in_old(&self) -> bool {
+        match *self {
+            Line::Added(_) => false,
+            _ => true,
+        }
+    }
+
+    fn in_new(&self) -> bool {
+        match *self {
+            Line::Removed(_) => false,
+            _ => true,
+        }
+    }
+}
+
+/// The shortest edit script from `old` to `new`, from their longest common subsequence.
+fn edits<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Line<'a>> {
+    // lcs[i][j] is the length of the longest common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                ::std::cmp::max(lcs[i + 1][j], lcs[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut lines = Vec::new();
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push(Line::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            lines.push(Line::Removed(old[i]));
+            i += 1;
+        } else {
+            lines.push(Line::Added(new[j]));
+            j += 1;
+        }
+    }
+    lines
+}
+
+/// A unified diff of `old` and `new`, or an empty string if they are equal.
+pub fn unified(path: &str, old: &str, new: &str) -> String {
+    let old_lines = old.lines().collect::<Vec<_>>();
+    let new_lines = new.lines().collect::<Vec<_>>();
+    let lines = edits(&old_lines, &new_lines);
+    if lines.iter().all(|l| l.in_old() && l.in_new()) {
+        return String::new();
+    }
+
+    let mut out = vec![format!("--- {}", path), format!("+++ {}", path)];
+    let changed = lines.iter().enumerate()
+        .filter(|&(_, l)| !(l.in_old() && l.in_new()))
+        .map(|(n, _)| n)
+        .collect::<Vec<_>>();
+    let mut k = 0;
+    while k < changed.len() {
+        // Changes less than two contexts apart share a hunk
+        let start = changed[k].saturating_sub(CONTEXT);
+        let mut last = changed[k];
+        while k + 1 < changed.len() && changed[k + 1] <= last + 2 * CONTEXT {
+            k += 1;
+            last = changed[k];
+        }
+        let end = ::std::cmp::min(last + CONTEXT + 1, lines.len());
+        k += 1;
+
+        // Line numbers of the hunk start in old and new
+        let old_start = lines[..start].iter().filter(|l| l.in_old()).count();
+        let new_start = lines[..start].iter().filter(|l| l.in_new()).count();
+        let hunk = &lines[start..end];
+        let old_len = hunk.iter().filter(|l| l.in_old()).count();
+        let new_len = hunk.iter().filter(|l| l.in_new()).count();
+        out.push(format!("@@ -{},{} +{},{} @@",
+                         if old_len == 0 { old_start } else { old_start + 1 }, old_len,
+                         if new_len == 0 { new_start } else { new_start + 1 }, new_len));
+        for line in hunk {
+            out.push(match *line {
+                Line::Same(text) => format!(" {}", text),
+                Line::Removed(text) => format!("-{}", text),
+                Line::Added(text) => format!("+{}", text),
+            });
+        }
+    }
+    out.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered(lines: ::std::ops::Range<u32>) -> Vec<String> {
+        lines.map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn unified_is_empty_for_equal_texts() {
+        assert_eq!(unified("f", "a\nb\n", "a\nb\n"), "");
+    }
+
+    #[test]
+    fn unified_shows_added_lines() {
+        assert_eq!(unified("f", "a\nb\n", "a\nb\nc\n"), "--- f\n+++ f\n@@ -1,2 +1,3 @@\n a\n b\n+c");
+    }
+
+    #[test]
+    fn unified_shows_removed_lines() {
+        assert_eq!(unified("f", "a\nb\nc\n", "a\nc\n"), "--- f\n+++ f\n@@ -1,3 +1,2 @@\n a\n-b\n c");
+    }
+
+    #[test]
+    fn unified_starts_an_empty_old_file_at_line_zero() {
+        assert_eq!(unified("f", "", "a\nb\n"), "--- f\n+++ f\n@@ -0,0 +1,2 @@\n+a\n+b");
+    }
+
+    #[test]
+    fn unified_merges_changes_that_share_context() {
+        let old = numbered(1..11);
+        let mut new = old.clone();
+        new[1] = "x".into();
+        new[7] = "y".into();
+        assert_eq!(unified("f", &old.join("\n"), &new.join("\n")),
+                   "--- f\n+++ f\n@@ -1,10 +1,10 @@\n 1\n-2\n+x\n 3\n 4\n 5\n 6\n 7\n-8\n+y\n 9\n 10");
+    }
+
+    #[test]
+    fn unified_splits_changes_far_apart_into_hunks() {
+        let old = numbered(1..21);
+        let mut new = old.clone();
+        new[1] = "x".into();
+        new[14] = "y".into();
+        assert_eq!(unified("f", &old.join("\n"), &new.join("\n")),
+                   concat!("--- f\n+++ f\n",
+                           "@@ -1,5 +1,5 @@\n 1\n-2\n+x\n 3\n 4\n 5\n",
+                           "@@ -12,7 +12,7 @@\n 12\n 13\n 14\n-15\n+y\n 16\n 17\n 18"));
+    }
+}
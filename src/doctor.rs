@@ -0,0 +1,223 @@
+//! `cargo teensy doctor`: checks that everything needed to build and upload is in place.
+
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use halfkay::VENDOR_ID;
+#[cfg(not(target_os = "linux"))]
+use hid::check_access;
+use tools;
+#[cfg(target_os = "linux")]
+use usb::check_access;
+
+/// Where udev looks for rules, in the order it reads them.
+const UDEV_RULE_DIRS: &'static [&'static str] = &["/etc/udev/rules.d", "/run/udev/rules.d",
+                                                  "/lib/udev/rules.d", "/usr/lib/udev/rules.d"];
+
+const UDEV_FIX: &'static str = "Install the Teensy udev rules:\n  \
+     sudo curl -o /etc/udev/rules.d/00-teensy.rules https://www.pjrc.com/teensy/00-teensy.rules\n  \
+     sudo udevadm control --reload-rules\n\
+     Then unplug the Teensy and plug it in again.";
+
+const BUSY_FIX: &'static str = "Quit other programs that talk to the Teensy, like the Teensy \
+     Loader, then unplug the Teensy and plug it in again.";
+
+pub struct Check {
+    pub name: String,
+    pub ok: bool,
+    /// The version found, or what is wrong.
+    pub detail: String,
+    pub fix: String,
+}
+
+impl Check {
+    pub fn pass(name: &str, detail: &str) -> Check {
+        Check { name: name.into(), ok: true, detail: detail.into(), fix: String::new() }
+    }
+
+    pub fn fail(name: &str, detail: &str, fix: &str) -> Check {
+        Check { name: name.into(), ok: false, detail: detail.into(), fix: fix.into() }
+    }
+}
+
+/// Runs `program` with `args` and returns the first line it prints.
+fn first_line(program: &str, args: &[&str]) -> Result<String, String> {
+    let output = try!(Command::new(program).args(args).output().map_err(|e| format!("{}", e)));
+    if !output.status.success() {
+        return Err(format!("`{} {}` failed", program, args.join(" ")));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).lines().next().unwrap_or("").trim().into())
+}
+
+/// Checks that `program` can be started and prints its version.
+pub fn tool(program: &str) -> Check {
+    match first_line(program, &["--version"]) {
+        Ok(version) => Check::pass(program, &version),
+        Err(_) => Check::fail(program, "not found", &tools::install_hint(program)),
+    }
+}
+
+/// Checks that `linker` finds the newlib libraries the target specification links against.
+pub fn newlib(linker: &str) -> Check {
+    match first_line(linker, &["-print-file-name=libnosys.a"]) {
+        // gcc prints the bare name if it cannot find the file.
+        Ok(ref path) if Path::new(path).is_absolute() => Check::pass("newlib", path),
+        Ok(_) => Check::fail("newlib", &format!("libnosys.a not found by {}", linker),
+                             &tools::install_hint("arm-none-eabi-gcc")),
+        Err(_) => Check::fail("newlib", &format!("cannot ask {}", linker),
+                              &tools::install_hint(linker)),
+    }
+}
+
+/// Where the OpenSSL headers are usually installed, if pkg-config does not know.
+const INCLUDE_DIRS: &'static [&'static str] = &["/usr/include", "/usr/local/include"];
+
+/// The directories openssl-sys looks for the OpenSSL headers in.
+fn openssl_include_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(dir) = env::var_os("OPENSSL_INCLUDE_DIR") {
+        dirs.push(PathBuf::from(dir));
+    }
+    if let Some(dir) = env::var_os("OPENSSL_DIR") {
+        dirs.push(Path::new(&dir).join("include"));
+    }
+    if let Ok(output) = Command::new("pkg-config").arg("--cflags-only-I").arg("openssl").output() {
+        let flags = String::from_utf8_lossy(&output.stdout).into_owned();
+        dirs.extend(flags.split_whitespace()
+                    .filter(|f| f.starts_with("-I"))
+                    .map(|f| PathBuf::from(&f[2..])));
+    }
+    dirs.extend(INCLUDE_DIRS.iter().map(PathBuf::from));
+    dirs
+}
+
+/// Checks that the OpenSSL headers, which curl needs to build cargo-teensy
+/// from source, are installed. On macOS curl uses the system's TLS instead.
+pub fn openssl_headers() -> Check {
+    let name = "openssl headers";
+    match openssl_include_dirs().into_iter().map(|d| d.join("openssl/ssl.h")).find(|h| h.exists()) {
+        Some(header) => Check::pass(name, &header.display().to_string()),
+        None => Check::fail(name, "openssl/ssl.h not found", &tools::install_hint("openssl")),
+    }
+}
+
+/// Checks that the standard library for `target` is installed with rustup,
+/// for `toolchain` or for the active toolchain if it is `None`.
+pub fn rustup_target(target: &str, toolchain: Option<&str>) -> Check {
+    let name = format!("target {}", target);
+    let mut command = Command::new("rustup");
+    command.arg("target").arg("list").arg("--installed");
+    if let Some(toolchain) = toolchain {
+        command.arg("--toolchain").arg(toolchain);
+    }
+    let output = match command.output() {
+        Ok(output) => output,
+        Err(_) => return Check::fail(&name, "rustup not found", &tools::install_hint("rustup")),
+    };
+    if String::from_utf8_lossy(&output.stdout).lines().any(|l| l.trim() == target) {
+        Check::pass(&name, "installed")
+    } else {
+        let option = toolchain.map_or(String::new(), |t| format!(" --toolchain {}", t));
+        Check::fail(&name, "not installed",
+                    &format!("Install with: rustup target add{} {}", option, target))
+    }
+}
+
+/// Checks that rustup installed `components` for `toolchain`, or for the
+/// active toolchain if it is `None`.
+pub fn rustup_components(toolchain: Option<&str>, components: &[String]) -> Check {
+    let name = "components";
+    if components.is_empty() {
+        return Check::pass(name, "none required");
+    }
+    let mut command = Command::new("rustup");
+    command.arg("component").arg("list").arg("--installed");
+    if let Some(toolchain) = toolchain {
+        command.arg("--toolchain").arg(toolchain);
+    }
+    let output = match command.output() {
+        Ok(output) => output,
+        Err(_) => return Check::fail(name, "rustup not found", &tools::install_hint("rustup")),
+    };
+    let (which, option) = match toolchain {
+        Some(toolchain) => (toolchain.to_string(), format!(" --toolchain {}", toolchain)),
+        None => ("the active toolchain".to_string(), String::new()),
+    };
+    if !output.status.success() {
+        return Check::fail(name, &format!("cannot list the components of {}", which),
+                           &format!("Install the toolchain with: rustup toolchain install {}",
+                                    toolchain.unwrap_or("<toolchain>")));
+    }
+    // Components are listed with the host appended, like `rust-src` or
+    // `llvm-tools-x86_64-unknown-linux-gnu`.
+    let installed = String::from_utf8_lossy(&output.stdout).into_owned();
+    let missing = components.iter().filter(|c| {
+        !installed.lines().map(|l| l.trim())
+            .any(|l| l == &c[..] || l.starts_with(&format!("{}-", c)))
+    }).cloned().collect::<Vec<_>>();
+    if missing.is_empty() {
+        Check::pass(name, &components.join(", "))
+    } else {
+        Check::fail(name, &format!("{} not installed for {}", missing.join(", "), which),
+                    &format!("Install with: rustup component add{} {}", option, missing.join(" ")))
+    }
+}
+
+/// Whether one of the udev rules mentions the Teensy vendor id.
+fn has_udev_rule() -> bool {
+    let vendor = format!("{:04x}", VENDOR_ID);
+    for dir in UDEV_RULE_DIRS {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.filter_map(|e| e.ok()) {
+            let mut rules = String::new();
+            if fs::File::open(entry.path()).and_then(|mut f| f.read_to_string(&mut rules)).is_ok()
+               && rules.to_lowercase().contains(&vendor) {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Checks that the Teensy can be opened without root. A connected Teensy is
+/// opened to be sure, otherwise the udev rules are looked for.
+pub fn usb_access() -> Check {
+    let name = "usb permissions";
+    match check_access() {
+        Ok(Some(product_id)) => {
+            return Check::pass(name, &format!("{:04x}:{:04x} can be opened", VENDOR_ID, product_id));
+        }
+        Ok(None) => {}
+        Err(ref e) if cfg!(target_os = "linux") => return Check::fail(name, e, UDEV_FIX),
+        Err(e) => return Check::fail(name, &e, BUSY_FIX),
+    }
+    if !cfg!(target_os = "linux") {
+        Check::pass(name, "no Teensy connected")
+    } else if has_udev_rule() {
+        Check::pass(name, "udev rule found, no Teensy connected")
+    } else {
+        Check::fail(name, &format!("no udev rule for {:04x}", VENDOR_ID), UDEV_FIX)
+    }
+}
+
+/// The checks as a table, followed by the fixes for the failed ones.
+pub fn report(checks: &[Check]) -> String {
+    let width = checks.iter().map(|c| c.name.len()).max().unwrap_or(0);
+    let mut lines = Vec::new();
+    for check in checks {
+        lines.push(format!("{}  {:width$}  {}", if check.ok { "PASS" } else { "FAIL" },
+                           check.name, check.detail, width = width));
+    }
+    for check in checks.iter().filter(|c| !c.ok) {
+        lines.push(String::new());
+        lines.push(format!("{}:", check.name));
+        lines.extend(check.fix.lines().map(|l| format!("  {}", l)));
+    }
+    lines.join("\n")
+}
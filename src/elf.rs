@@ -0,0 +1,239 @@
+//! Minimal reader for 32 bit little endian ELF files, as produced for the Teensy.
+
+use std::fs::File;
+use std::io::Read;
+
+use error::Error;
+
+const PT_LOAD: u32 = 1;
+pub const SHT_NOBITS: u32 = 8;
+pub const SHF_WRITE: u32 = 0x1;
+pub const SHF_ALLOC: u32 = 0x2;
+pub const SHF_EXECINSTR: u32 = 0x4;
+
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub kind: u32,
+    pub offset: u32,
+    pub paddr: u32,
+    pub filesz: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub name: String,
+    pub kind: u32,
+    pub flags: u32,
+    pub addr: u32,
+    pub offset: u32,
+    pub size: u32,
+}
+
+pub struct Elf {
+    pub segments: Vec<Segment>,
+    pub sections: Vec<Section>,
+    data: Vec<u8>,
+}
+
+fn u16_at(data: &[u8], pos: usize) -> Result<u16, String> {
+    if pos + 2 > data.len() {
+        return Err("truncated ELF file".into());
+    }
+    Ok(data[pos] as u16 | (data[pos + 1] as u16) << 8)
+}
+
+fn u32_at(data: &[u8], pos: usize) -> Result<u32, String> {
+    if pos + 4 > data.len() {
+        return Err("truncated ELF file".into());
+    }
+    Ok(data[pos] as u32 | (data[pos + 1] as u32) << 8 |
+       (data[pos + 2] as u32) << 16 | (data[pos + 3] as u32) << 24)
+}
+
+fn name_at(strtab: &[u8], pos: usize) -> String {
+    if pos >= strtab.len() {
+        return String::new();
+    }
+    let end = strtab[pos..].iter().position(|b| *b == 0).map_or(strtab.len(), |e| pos + e);
+    String::from_utf8_lossy(&strtab[pos..end]).into_owned()
+}
+
+impl Elf {
+    pub fn parse(data: Vec<u8>) -> Result<Elf, String> {
+        if data.len() < 52 || &data[0..4] != b"\x7fELF" {
+            return Err("not an ELF file".into());
+        }
+        if data[4] != 1 || data[5] != 1 {
+            return Err("not a 32 bit little endian ELF file".into());
+        }
+        let phoff = try!(u32_at(&data, 28)) as usize;
+        let shoff = try!(u32_at(&data, 32)) as usize;
+        let phentsize = try!(u16_at(&data, 42)) as usize;
+        let phnum = try!(u16_at(&data, 44)) as usize;
+        let shentsize = try!(u16_at(&data, 46)) as usize;
+        let shnum = try!(u16_at(&data, 48)) as usize;
+        let shstrndx = try!(u16_at(&data, 50)) as usize;
+
+        let mut segments = Vec::with_capacity(phnum);
+        for i in 0..phnum {
+            let ph = phoff + i * phentsize;
+            segments.push(Segment {
+                kind: try!(u32_at(&data, ph)),
+                offset: try!(u32_at(&data, ph + 4)),
+                paddr: try!(u32_at(&data, ph + 12)),
+                filesz: try!(u32_at(&data, ph + 16)),
+            });
+        }
+
+        let mut raw_sections = Vec::with_capacity(shnum);
+        for i in 0..shnum {
+            let sh = shoff + i * shentsize;
+            raw_sections.push((try!(u32_at(&data, sh)) as usize, Section {
+                name: String::new(),
+                kind: try!(u32_at(&data, sh + 4)),
+                flags: try!(u32_at(&data, sh + 8)),
+                addr: try!(u32_at(&data, sh + 12)),
+                offset: try!(u32_at(&data, sh + 16)),
+                size: try!(u32_at(&data, sh + 20)),
+            }));
+        }
+        let strtab = match raw_sections.get(shstrndx) {
+            Some(&(_, ref s)) if s.kind != SHT_NOBITS => {
+                let start = s.offset as usize;
+                let end = start + s.size as usize;
+                if end > data.len() {
+                    return Err("truncated ELF file".into());
+                }
+                data[start..end].to_vec()
+            }
+            _ => Vec::new(),
+        };
+        let sections = raw_sections.into_iter().map(|(name, mut s)| {
+            s.name = name_at(&strtab, name);
+            s
+        }).collect();
+
+        Ok(Elf { segments: segments, sections: sections, data: data })
+    }
+
+    /// The load address (LMA) of an allocated section, which differs from its
+    /// run address for initialized data that is copied from flash to RAM at startup.
+    fn load_address(&self, section: &Section) -> u32 {
+        for seg in &self.segments {
+            if seg.kind == PT_LOAD && section.offset >= seg.offset &&
+               section.offset + section.size <= seg.offset + seg.filesz {
+                return seg.paddr + (section.offset - seg.offset);
+            }
+        }
+        section.addr
+    }
+
+    /// The bytes that have to be programmed, as `(load address, bytes)` runs.
+    /// Sections named in `exclude` are left out.
+    pub fn load_image(&self, exclude: &[&str]) -> Result<Vec<(u32, Vec<u8>)>, String> {
+        let mut image = Vec::new();
+        for section in &self.sections {
+            if section.flags & SHF_ALLOC == 0 || section.kind == SHT_NOBITS || section.size == 0 {
+                continue;
+            }
+            if exclude.iter().any(|e| *e == section.name) {
+                continue;
+            }
+            let start = section.offset as usize;
+            let end = start + section.size as usize;
+            if end > self.data.len() {
+                return Err(format!("section {} lies outside of the ELF file", section.name));
+            }
+            image.push((self.load_address(section), self.data[start..end].to_vec()));
+        }
+        image.sort_by_key(|&(addr, _)| addr);
+        Ok(image)
+    }
+}
+
+pub fn read_file(path: &str) -> Result<Elf, Error> {
+    let mut f = try!(File::open(path).map_err(|ioerr| Error::io(path, ioerr)));
+    let mut data = Vec::new();
+    try!(f.read_to_end(&mut data).map_err(|ioerr| Error::io(path, ioerr)));
+    Elf::parse(data).map_err(|e| Error::Firmware(format!("{}: {}", path, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHT_PROGBITS: u32 = 1;
+    const SHT_STRTAB: u32 = 3;
+
+    fn push_u16(out: &mut Vec<u8>, v: u16) {
+        out.extend_from_slice(&[v as u8, (v >> 8) as u8]);
+    }
+
+    fn push_u32(out: &mut Vec<u8>, v: u32) {
+        out.extend_from_slice(&[v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8]);
+    }
+
+    /// `.text` at 0 and `.data`, which runs at 0x1FFF8000 in RAM but is
+    /// loaded into flash right behind `.text`, as the linker lays out a Teensy.
+    fn teensy_elf() -> Vec<u8> {
+        let strtab = b"\0.text\0.data\0.shstrtab\0";
+        let (text_at, data_at, strtab_at) = (116, 120, 124);
+        let shoff = 148;
+
+        let mut out = Vec::new();
+        out.extend_from_slice(b"\x7fELF\x01\x01\x01\0\0\0\0\0\0\0\0\0");
+        push_u16(&mut out, 2); // executable
+        push_u16(&mut out, 40); // ARM
+        push_u32(&mut out, 1);
+        push_u32(&mut out, 0); // entry
+        push_u32(&mut out, 52); // program headers
+        push_u32(&mut out, shoff);
+        push_u32(&mut out, 0);
+        push_u16(&mut out, 52);
+        push_u16(&mut out, 32);
+        push_u16(&mut out, 2);
+        push_u16(&mut out, 40);
+        push_u16(&mut out, 4);
+        push_u16(&mut out, 3);
+
+        // type, offset, vaddr, paddr, filesz, memsz, flags, align
+        for &(offset, vaddr, paddr) in &[(text_at, 0, 0), (data_at, 0x1FFF_8000, 4)] {
+            for v in &[PT_LOAD, offset, vaddr, paddr, 4, 4, 0, 4] {
+                push_u32(&mut out, *v);
+            }
+        }
+
+        out.extend_from_slice(&[0xAA, 0xBB, 0xCC, 0xDD]);
+        out.extend_from_slice(&[0x11, 0x22, 0x33, 0x44]);
+        out.extend_from_slice(strtab);
+        out.push(0);
+        assert_eq!(out.len(), shoff as usize);
+
+        // name, type, flags, addr, offset, size, link, info, addralign, entsize
+        let sections = [[0; 6],
+                        [1, SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR, 0, text_at, 4],
+                        [7, SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 0x1FFF_8000, data_at, 4],
+                        [13, SHT_STRTAB, 0, 0, strtab_at, strtab.len() as u32]];
+        for section in &sections {
+            for v in section.iter().chain(&[0, 0, 0, 0]) {
+                push_u32(&mut out, *v);
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn load_image_places_data_at_its_load_address() {
+        let elf = Elf::parse(teensy_elf()).unwrap();
+        let data = elf.sections.iter().find(|s| s.name == ".data").unwrap();
+        assert_eq!(data.addr, 0x1FFF_8000);
+        assert_eq!(elf.load_image(&[]),
+                   Ok(vec![(0, vec![0xAA, 0xBB, 0xCC, 0xDD]), (4, vec![0x11, 0x22, 0x33, 0x44])]));
+    }
+
+    #[test]
+    fn load_image_leaves_out_excluded_sections() {
+        let elf = Elf::parse(teensy_elf()).unwrap();
+        assert_eq!(elf.load_image(&[".data"]), Ok(vec![(0, vec![0xAA, 0xBB, 0xCC, 0xDD])]));
+    }
+}
@@ -0,0 +1,182 @@
+//! Everything that can go wrong, with a hint for the user and an exit code for scripts.
+
+use std::fmt;
+use std::io;
+
+use toolchain::{Comparison, Toolchain};
+use tools;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Invalid command line flags or settings.
+    Usage(String),
+    /// Cargo.toml is missing, malformed or has invalid `[package.metadata.teensy]` settings.
+    Manifest(String),
+    /// An external program is not installed.
+    MissingTool(String),
+    /// Reading or writing `path` failed.
+    Io(String, io::Error),
+    /// Downloading `url` failed.
+    Network(String, String),
+    /// A subprocess exited unsuccessfully. Holds the command line and its exit code.
+    Failed(String, Option<i32>),
+    /// A subprocess was terminated by a signal. Holds the command line and the signal number.
+    Killed(String, i32),
+    /// The built ELF or HEX file cannot be used.
+    Firmware(String),
+    /// Talking to the Teensy failed.
+    Device(String),
+    /// The firmware uses more flash or RAM than allowed.
+    Budget(String),
+    /// The installed rust version is not the required one. Holds installed and required version.
+    RustVersion(Toolchain, Toolchain),
+    /// The installed rust version cannot be told from `rustc -vV`. Holds why.
+    UnknownRustVersion(String),
+    /// Some checks of `cargo teensy doctor` failed. Holds how many.
+    Unhealthy(usize),
+}
+
+impl Error {
+    pub fn io(path: &str, err: io::Error) -> Error {
+        Error::Io(path.into(), err)
+    }
+
+    pub fn message(&self) -> String {
+        match *self {
+            Error::Usage(ref msg) => msg.clone(),
+            Error::Manifest(ref msg) => format!("Cargo.toml: {}", msg),
+            Error::MissingTool(ref tool) => format!("`{}` is not installed or not in $PATH", tool),
+            Error::Io(ref path, ref err) => format!("{}: {}", path, err),
+            Error::Network(ref url, ref msg) => format!("Could not download {}: {}", url, msg),
+            Error::Failed(ref command, Some(code)) => {
+                format!("Command failed with exit code {}: {}", code, command)
+            }
+            Error::Failed(ref command, None) => format!("Command failed: {}", command),
+            Error::Killed(ref command, signal) => {
+                format!("Command was killed by {}: {}", signal_name(signal), command)
+            }
+            Error::Firmware(ref msg) => msg.clone(),
+            Error::Device(ref msg) => format!("Upload failed: {}", msg),
+            Error::Budget(ref msg) => msg.clone(),
+            Error::RustVersion(ref installed, ref required) => {
+                match installed.compare(required) {
+                    Comparison::Older => {
+                        format!("Installed rust {} is older than the required {}.",
+                                installed, required)
+                    }
+                    Comparison::Newer => {
+                        format!("Installed rust {} is newer than the required {}.",
+                                installed, required)
+                    }
+                    Comparison::DifferentHost => {
+                        format!("Installed rust {} is built for {}, but the required one for {}.",
+                                installed, installed.host.as_ref().map_or("", |h| &h[..]),
+                                required.host.as_ref().map_or("", |h| &h[..]))
+                    }
+                    Comparison::DifferentChannel | Comparison::Same => {
+                        format!("Installed rust {} is from the {} channel, but the required {} \
+                                 is from the {} channel.", installed, installed.channel_name(),
+                                required, required.channel_name())
+                    }
+                }
+            }
+            Error::UnknownRustVersion(ref msg) => {
+                format!("Cannot tell the installed rust version: {}", msg)
+            }
+            Error::Unhealthy(1) => "1 check failed".into(),
+            Error::Unhealthy(failed) => format!("{} checks failed", failed),
+        }
+    }
+
+    pub fn fix(&self) -> String {
+        match *self {
+            Error::Usage(_) => "See `cargo teensy --help`.".into(),
+            Error::Manifest(_) => {
+                "Run cargo teensy inside a cargo project and check Cargo.toml for mistakes.".into()
+            }
+            Error::MissingTool(ref tool) => tools::install_hint(tool),
+            Error::Io(..) => {
+                "Check that the path exists and that you are allowed to access it.".into()
+            }
+            Error::Network(..) => {
+                "Check your internet connection, or use the cached version with --offline.".into()
+            }
+            Error::Failed(..) => "Fix the problems reported above and try again.".into(),
+            Error::Killed(..) => {
+                "The command was interrupted or crashed. Run it again, and check for lack \
+                 of memory if it keeps happening.".into()
+            }
+            Error::Firmware(_) => "Run `cargo clean` and build again.".into(),
+            Error::Device(_) => {
+                "Check the USB cable and your permissions on the device (udev rules), \
+                 then press the reset button on the Teensy.".into()
+            }
+            Error::Budget(_) => {
+                "Reduce the memory usage or raise --flash-budget / --ram-budget.".into()
+            }
+            Error::RustVersion(_, ref required) => {
+                format!("Select it with: rustup override set {0}\n\
+                         With rustup installed, `cargo teensy new` pins it in rust-toolchain.toml.\n\
+                         Or use: cargo teensy new --ignore-version", required)
+            }
+            Error::UnknownRustVersion(_) => {
+                "Check that `rustc -vV` works, or skip the check with --ignore-version.".into()
+            }
+            Error::Unhealthy(_) => {
+                "Follow the suggestions above and run `cargo teensy doctor` again.".into()
+            }
+        }
+    }
+
+    /// The exit code of cargo-teensy for this error. Each kind of error has its own.
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            Error::Usage(_) => 2,
+            Error::Manifest(_) => 3,
+            Error::MissingTool(_) => 4,
+            Error::Io(..) => 5,
+            Error::Network(..) => 6,
+            Error::Failed(..) => 7,
+            Error::Firmware(_) => 8,
+            Error::Device(_) => 9,
+            Error::Budget(_) => 10,
+            Error::RustVersion(..) => 11,
+            Error::Killed(..) => 12,
+            Error::Unhealthy(_) => 13,
+            Error::UnknownRustVersion(_) => 14,
+        }
+    }
+}
+
+/// The name of a unix signal, like `SIGKILL`.
+#[cfg(unix)]
+pub fn signal_name(signal: i32) -> String {
+    use libc;
+    let name = match signal {
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGINT => "SIGINT",
+        libc::SIGQUIT => "SIGQUIT",
+        libc::SIGILL => "SIGILL",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGFPE => "SIGFPE",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGPIPE => "SIGPIPE",
+        libc::SIGALRM => "SIGALRM",
+        libc::SIGTERM => "SIGTERM",
+        _ => return format!("signal {}", signal),
+    };
+    name.into()
+}
+
+#[cfg(not(unix))]
+pub fn signal_name(signal: i32) -> String {
+    format!("signal {}", signal)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
@@ -0,0 +1,371 @@
+//! The HalfKay bootloader protocol spoken by the Teensy bootloader chip.
+//!
+//! The firmware is sent as a sequence of HID reports. Each report carries one
+//! flash block prefixed by its address. Reports are written over a `Transport`,
+//! so the protocol does not care whether it talks to real hardware or to a fake.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const VENDOR_ID: u16 = 0x16C0;
+pub const HALFKAY_PRODUCT_ID: u16 = 0x0478;
+/// USB serial is not HID, so only the libusb transport of Linux opens it.
+#[cfg(target_os = "linux")]
+pub const SERIAL_PRODUCT_ID: u16 = 0x0483;
+pub const REBOOTOR_PRODUCT_ID: u16 = 0x0477;
+
+/// The first block erases the whole flash before it is written, which takes a while.
+const FIRST_BLOCK_TIMEOUT_MS: u64 = 5000;
+const BLOCK_TIMEOUT_MS: u64 = 500;
+const RETRY_DELAY_MS: u64 = 10;
+const WAIT_POLL_MS: u64 = 250;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mcu {
+    pub name: &'static str,
+    pub code_size: usize,
+    pub block_size: usize,
+}
+
+const MCUS: &'static [Mcu] = &[
+    Mcu { name: "mk20dx128", code_size: 131072, block_size: 1024 },
+    Mcu { name: "mk20dx256", code_size: 262144, block_size: 1024 },
+    Mcu { name: "mkl26z64", code_size: 63488, block_size: 512 },
+    Mcu { name: "mk64fx512", code_size: 524288, block_size: 1024 },
+    Mcu { name: "mk66fx1m0", code_size: 1048576, block_size: 1024 },
+];
+
+pub fn mcu(name: &str) -> Option<Mcu> {
+    MCUS.iter().find(|m| m.name == name).cloned()
+}
+
+impl Mcu {
+    fn header_size(&self) -> usize {
+        if self.block_size <= 256 { 2 } else { 64 }
+    }
+
+    pub fn report_size(&self) -> usize {
+        self.block_size + self.header_size()
+    }
+
+    /// Builds the report that writes `data` (at most one block) to flash address `addr`.
+    pub fn block_report(&self, addr: usize, data: &[u8]) -> Vec<u8> {
+        let mut report = vec![0u8; self.report_size()];
+        match self.block_size {
+            128 => {
+                report[0] = addr as u8;
+                report[1] = (addr >> 8) as u8;
+            }
+            256 => {
+                report[0] = (addr >> 8) as u8;
+                report[1] = (addr >> 16) as u8;
+            }
+            _ => {
+                report[0] = addr as u8;
+                report[1] = (addr >> 8) as u8;
+                report[2] = (addr >> 16) as u8;
+            }
+        }
+        let header = self.header_size();
+        report[header..header + data.len()].copy_from_slice(data);
+        report
+    }
+
+    /// The report that makes HalfKay leave the bootloader and start the new firmware.
+    pub fn reboot_report(&self) -> Vec<u8> {
+        let mut report = vec![0u8; self.report_size()];
+        report[0] = 0xFF;
+        report[1] = 0xFF;
+        report[2] = 0xFF;
+        report
+    }
+}
+
+/// A flash image. Bytes that the hex file did not set stay erased (0xFF).
+pub struct Image {
+    data: Vec<u8>,
+    used: Vec<bool>,
+}
+
+impl Image {
+    pub fn new(segments: &[(u32, Vec<u8>)], code_size: usize) -> Result<Image, String> {
+        let mut image = Image { data: vec![0xFF; code_size], used: vec![false; code_size] };
+        for &(addr, ref bytes) in segments {
+            let start = addr as usize;
+            let end = start + bytes.len();
+            if end > code_size {
+                return Err(format!("Firmware does not fit into flash: data at 0x{:08X}..0x{:08X}, \
+                                    flash size is 0x{:08X}", start, end, code_size));
+            }
+            image.data[start..end].copy_from_slice(bytes);
+            for u in &mut image.used[start..end] {
+                *u = true;
+            }
+        }
+        Ok(image)
+    }
+
+    fn block_needed(&self, addr: usize, len: usize) -> bool {
+        (addr..addr + len).any(|i| self.used[i] && self.data[i] != 0xFF)
+    }
+}
+
+/// Raw access to a Teensy. `usb::UsbTransport` (Linux) and `hid::HidTransport`
+/// (macOS and Windows) implement it for real hardware.
+pub trait Transport {
+    /// Opens the HalfKay bootloader. Returns `Ok(false)` if no bootloader is attached.
+    fn open(&mut self) -> Result<bool, String>;
+    /// Sends one HID report. Fails while the device is busy.
+    fn write(&mut self, report: &[u8]) -> Result<(), String>;
+    fn close(&mut self);
+    /// Asks a running Teensy 3.x sketch with USB serial to enter the bootloader.
+    fn soft_reboot(&mut self) -> Result<bool, String>;
+    /// Asks an attached rebootor to pull the reset line.
+    fn hard_reboot(&mut self) -> Result<bool, String>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reboot {
+    None,
+    Soft,
+    Hard,
+}
+
+pub struct Options {
+    /// Wait for the bootloader to appear instead of failing immediately.
+    pub wait: bool,
+    /// How to get the Teensy into the bootloader if it is not there yet.
+    pub reboot: Reboot,
+    /// Start the new firmware after programming.
+    pub boot: bool,
+    pub verbose: bool,
+}
+
+/// Retries `report` until the device accepts it or `timeout` has passed.
+fn write_with_retry<T: Transport>(transport: &mut T, report: &[u8], timeout: Duration)
+                                  -> Result<(), String> {
+    let start = Instant::now();
+    loop {
+        match transport.write(report) {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                if start.elapsed() >= timeout {
+                    return Err(e);
+                }
+            }
+        }
+        thread::sleep(Duration::from_millis(RETRY_DELAY_MS));
+    }
+}
+
+fn wait_for_bootloader<T: Transport>(transport: &mut T, options: &Options) -> Result<(), String> {
+    let mut reboot = options.reboot;
+    let mut wait = options.wait;
+    let mut waited = false;
+    loop {
+        if try!(transport.open()) {
+            return Ok(());
+        }
+        match reboot {
+            Reboot::Hard => {
+                if !try!(transport.hard_reboot()) {
+                    return Err("Unable to find rebootor".into());
+                }
+                wait = true;
+            }
+            Reboot::Soft => {
+                if try!(transport.soft_reboot()) && options.verbose {
+                    println!("Soft reboot performed");
+                }
+                wait = true;
+            }
+            Reboot::None => {}
+        }
+        reboot = Reboot::None;
+        if !wait {
+            return Err("Unable to open device (hint: press the reset button)".into());
+        }
+        if !waited {
+            println!("Waiting for Teensy device...\n (hint: press the reset button)");
+            waited = true;
+        }
+        thread::sleep(Duration::from_millis(WAIT_POLL_MS));
+    }
+}
+
+/// Writes `image` to the Teensy. Returns the number of blocks written.
+pub fn program<T: Transport>(transport: &mut T, mcu: &Mcu, image: &Image, options: &Options)
+                             -> Result<usize, String> {
+    if image.data.len() != mcu.code_size {
+        return Err(format!("Image size does not match the flash size of {}", mcu.name));
+    }
+    try!(wait_for_bootloader(transport, options));
+
+    let mut blocks = 0;
+    let mut addr = 0;
+    while addr < mcu.code_size {
+        let len = ::std::cmp::min(mcu.block_size, mcu.code_size - addr);
+        if blocks == 0 || image.block_needed(addr, len) {
+            let report = mcu.block_report(addr, &image.data[addr..addr + len]);
+            let timeout = if blocks == 0 { FIRST_BLOCK_TIMEOUT_MS } else { BLOCK_TIMEOUT_MS };
+            if let Err(e) = write_with_retry(transport, &report, Duration::from_millis(timeout)) {
+                transport.close();
+                return Err(format!("Error writing block at 0x{:06X}: {}", addr, e));
+            }
+            blocks += 1;
+        }
+        addr += mcu.block_size;
+    }
+
+    if options.boot {
+        // The device usually disconnects before acknowledging, so errors are expected here.
+        let report = mcu.reboot_report();
+        let _ = write_with_retry(transport, &report, Duration::from_millis(BLOCK_TIMEOUT_MS));
+    }
+    transport.close();
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    /// Records the reports instead of sending them. Writes fail until
+    /// `busy_until`, like a device that is still erasing.
+    struct FakeTransport {
+        present: bool,
+        busy_until: Option<Instant>,
+        /// Fail every write after this many reports.
+        fail_after: Option<usize>,
+        reports: Vec<Vec<u8>>,
+        closed: bool,
+    }
+
+    impl FakeTransport {
+        fn new() -> FakeTransport {
+            FakeTransport { present: true, busy_until: None, fail_after: None,
+                            reports: Vec::new(), closed: false }
+        }
+    }
+
+    impl Transport for FakeTransport {
+        fn open(&mut self) -> Result<bool, String> {
+            Ok(self.present)
+        }
+
+        fn write(&mut self, report: &[u8]) -> Result<(), String> {
+            if self.busy_until.map_or(false, |t| Instant::now() < t) {
+                return Err("busy".into());
+            }
+            if self.fail_after.map_or(false, |n| self.reports.len() >= n) {
+                return Err("gone".into());
+            }
+            self.reports.push(report.to_vec());
+            Ok(())
+        }
+
+        fn close(&mut self) {
+            self.closed = true;
+        }
+
+        fn soft_reboot(&mut self) -> Result<bool, String> {
+            Ok(false)
+        }
+
+        fn hard_reboot(&mut self) -> Result<bool, String> {
+            Ok(false)
+        }
+    }
+
+    fn options(boot: bool) -> Options {
+        Options { wait: false, reboot: Reboot::None, boot: boot, verbose: false }
+    }
+
+    fn address(mcu: &Mcu, report: &[u8]) -> usize {
+        match mcu.block_size {
+            128 => report[0] as usize | (report[1] as usize) << 8,
+            256 => (report[0] as usize) << 8 | (report[1] as usize) << 16,
+            _ => report[0] as usize | (report[1] as usize) << 8 | (report[2] as usize) << 16,
+        }
+    }
+
+    #[test]
+    fn block_report_encodes_the_address() {
+        let small = Mcu { name: "small", code_size: 32768, block_size: 128 };
+        assert_eq!(&small.block_report(0x1280, &[1])[..3], &[0x80, 0x12, 1]);
+        let medium = Mcu { name: "medium", code_size: 65536, block_size: 256 };
+        assert_eq!(&medium.block_report(0x12300, &[1])[..3], &[0x23, 0x01, 1]);
+        let k20 = mcu("mk20dx256").unwrap();
+        let report = k20.block_report(0x03_2400, &[0xAB, 0xCD]);
+        assert_eq!(report.len(), 1024 + 64);
+        assert_eq!(&report[..3], &[0x00, 0x24, 0x03]);
+        assert_eq!(&report[64..66], &[0xAB, 0xCD]);
+    }
+
+    #[test]
+    fn program_skips_blank_blocks() {
+        let mcu = mcu("mkl26z64").unwrap();
+        // One byte in the third block, and one erased byte in the fifth
+        let image = Image::new(&[(0x0400, vec![0x42]), (0x0800, vec![0xFF])], mcu.code_size)
+            .unwrap();
+        let mut transport = FakeTransport::new();
+        let blocks = program(&mut transport, &mcu, &image, &options(false)).unwrap();
+        // The first block is always written, it erases the flash
+        assert_eq!(blocks, 2);
+        let addresses = transport.reports.iter().map(|r| address(&mcu, r)).collect::<Vec<_>>();
+        assert_eq!(addresses, vec![0x0000, 0x0400]);
+        assert_eq!(transport.reports[1][64], 0x42);
+        assert!(transport.closed);
+    }
+
+    #[test]
+    fn first_block_waits_for_the_erase() {
+        let mcu = mcu("mk20dx128").unwrap();
+        let image = Image::new(&[(0, vec![1, 2, 3])], mcu.code_size).unwrap();
+        let mut transport = FakeTransport::new();
+        // Longer than other blocks may take, shorter than the first may
+        transport.busy_until = Some(Instant::now() + Duration::from_millis(BLOCK_TIMEOUT_MS * 2));
+        assert_eq!(program(&mut transport, &mcu, &image, &options(false)), Ok(1));
+    }
+
+    #[test]
+    fn later_blocks_time_out_sooner() {
+        let mcu = mcu("mk20dx128").unwrap();
+        let image = Image::new(&[(0, vec![1]), (0x2000, vec![2])], mcu.code_size).unwrap();
+        let mut transport = FakeTransport::new();
+        transport.fail_after = Some(1);
+        let start = Instant::now();
+        let result = program(&mut transport, &mcu, &image, &options(false));
+        assert_eq!(result, Err("Error writing block at 0x002000: gone".into()));
+        assert!(start.elapsed() < Duration::from_millis(FIRST_BLOCK_TIMEOUT_MS));
+        assert!(transport.closed);
+    }
+
+    #[test]
+    fn boot_sends_the_reboot_report() {
+        let mcu = mcu("mk20dx256").unwrap();
+        let image = Image::new(&[(0, vec![1])], mcu.code_size).unwrap();
+
+        let mut transport = FakeTransport::new();
+        program(&mut transport, &mcu, &image, &options(true)).unwrap();
+        let reboot = transport.reports.last().unwrap();
+        assert_eq!(reboot.len(), mcu.report_size());
+        assert_eq!(&reboot[..3], &[0xFF, 0xFF, 0xFF]);
+        assert!(reboot[3..].iter().all(|&b| b == 0));
+
+        let mut transport = FakeTransport::new();
+        program(&mut transport, &mcu, &image, &options(false)).unwrap();
+        assert_eq!(transport.reports.len(), 1);
+    }
+
+    #[test]
+    fn missing_bootloader_fails_without_wait() {
+        let mcu = mcu("mk20dx256").unwrap();
+        let image = Image::new(&[], mcu.code_size).unwrap();
+        let mut transport = FakeTransport::new();
+        transport.present = false;
+        assert!(program(&mut transport, &mcu, &image, &options(false)).is_err());
+        assert!(transport.reports.is_empty());
+    }
+}
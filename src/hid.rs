@@ -0,0 +1,126 @@
+//! HalfKay `Transport` over the HID API of the OS, for macOS and Windows.
+//!
+//! These keep the HID interface of the bootloader to themselves, so it is
+//! written through their HID API (with hidapi) instead of claimed with libusb.
+
+use hidapi::{HidApi, HidDevice};
+
+use halfkay::{Transport, VENDOR_ID, HALFKAY_PRODUCT_ID, REBOOTOR_PRODUCT_ID};
+
+/// The report id hidapi expects in front of each report. The Teensy
+/// devices have only one report, which has no id.
+const NO_REPORT_ID: u8 = 0;
+/// Makes a Teensy with USB serial jump into the bootloader.
+#[cfg(unix)]
+const REBOOT_BAUD: u32 = 134;
+
+pub struct HidTransport {
+    api: Option<HidApi>,
+    device: Option<HidDevice>,
+}
+
+impl HidTransport {
+    pub fn new() -> HidTransport {
+        HidTransport { api: None, device: None }
+    }
+
+    /// The HID context, with the devices attached right now.
+    fn api(&mut self) -> Result<&HidApi, String> {
+        match self.api {
+            Some(ref mut api) => try!(api.refresh_devices().map_err(|e| format!("{}", e))),
+            None => self.api = Some(try!(HidApi::new().map_err(|e| format!("{}", e)))),
+        }
+        Ok(self.api.as_ref().unwrap())
+    }
+
+    fn open_device(&mut self, product_id: u16) -> Result<Option<HidDevice>, String> {
+        let api = try!(self.api());
+        let info = match api.device_list()
+                            .find(|d| d.vendor_id() == VENDOR_ID && d.product_id() == product_id) {
+            Some(info) => info,
+            None => return Ok(None),
+        };
+        info.open_device(api).map(Some)
+            .map_err(|e| format!("Unable to open {:04x}:{:04x}: {}", VENDOR_ID, product_id, e))
+    }
+}
+
+fn write_report(device: &HidDevice, report: &[u8]) -> Result<(), String> {
+    let mut data = Vec::with_capacity(report.len() + 1);
+    data.push(NO_REPORT_ID);
+    data.extend_from_slice(report);
+    device.write(&data).map(|_| ()).map_err(|e| format!("{}", e))
+}
+
+impl Transport for HidTransport {
+    fn open(&mut self) -> Result<bool, String> {
+        if self.device.is_none() {
+            self.device = try!(self.open_device(HALFKAY_PRODUCT_ID));
+        }
+        Ok(self.device.is_some())
+    }
+
+    fn write(&mut self, report: &[u8]) -> Result<(), String> {
+        match self.device {
+            Some(ref device) => write_report(device, report),
+            None => Err("Device is not open".into()),
+        }
+    }
+
+    fn close(&mut self) {
+        self.device = None;
+    }
+
+    fn soft_reboot(&mut self) -> Result<bool, String> {
+        soft_reboot()
+    }
+
+    fn hard_reboot(&mut self) -> Result<bool, String> {
+        let device = match try!(self.open_device(REBOOTOR_PRODUCT_ID)) {
+            Some(device) => device,
+            None => return Ok(false),
+        };
+        // The rebootor resets the Teensy and may vanish before it answers.
+        let _ = write_report(&device, b"reboot");
+        Ok(true)
+    }
+}
+
+/// The USB serial interface is not HID. Setting its port to 134 baud tells
+/// the Teensy USB serial code to jump into the bootloader, like the line
+/// coding request on Linux.
+#[cfg(unix)]
+fn soft_reboot() -> Result<bool, String> {
+    use serial::{self, Port};
+
+    let path = match serial::find() {
+        Some(path) => path,
+        None => return Ok(false),
+    };
+    try!(Port::open(&path, REBOOT_BAUD)
+         .map_err(|e| format!("Soft reboot failed: {}: {}", path, e)));
+    Ok(true)
+}
+
+#[cfg(not(unix))]
+fn soft_reboot() -> Result<bool, String> {
+    Err("Soft reboot is not supported here, press the button on the Teensy instead".into())
+}
+
+/// Tries to open the first connected Teensy. Returns its product id, or
+/// `None` if no Teensy is connected.
+pub fn check_access() -> Result<Option<u16>, String> {
+    let api = try!(HidApi::new().map_err(|e| format!("Cannot list HID devices: {}", e)));
+    for product_id in &[HALFKAY_PRODUCT_ID, REBOOTOR_PRODUCT_ID] {
+        let info = match api.device_list()
+                            .find(|d| d.vendor_id() == VENDOR_ID && d.product_id() == *product_id) {
+            Some(info) => info,
+            None => continue,
+        };
+        return match info.open_device(&api) {
+            Ok(_) => Ok(Some(*product_id)),
+            Err(e) => Err(format!("Cannot open {:04x}:{:04x}: {}", VENDOR_ID, product_id, e)),
+        };
+    }
+    Ok(None)
+}
@@ -0,0 +1,163 @@
+//! Intel HEX files.
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::fmt::Write as FmtWrite;
+
+use error::Error;
+
+const DATA: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+const START_SEGMENT_ADDRESS: u8 = 0x03;
+const EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+const START_LINEAR_ADDRESS: u8 = 0x05;
+
+const BYTES_PER_RECORD: usize = 16;
+
+fn parse_record(line: &str) -> Result<(u8, u16, Vec<u8>), String> {
+    // The digits are sliced by byte below
+    if !line.is_ascii() {
+        return Err("not a hex record, it contains non-ASCII characters".into());
+    }
+    if !line.starts_with(':') || line.len() < 11 || line.len() % 2 == 0 {
+        return Err("not a hex record".into());
+    }
+    let mut bytes = Vec::with_capacity(line.len() / 2);
+    for i in 0..line.len() / 2 {
+        let digits = &line[1 + 2 * i..3 + 2 * i];
+        bytes.push(try!(u8::from_str_radix(digits, 16)
+            .map_err(|_| format!("invalid hex digits '{}'", digits))));
+    }
+    let len = bytes[0] as usize;
+    if bytes.len() != len + 5 {
+        return Err("record length does not match".into());
+    }
+    let checksum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    if checksum != 0 {
+        return Err("checksum mismatch".into());
+    }
+    let offset = ((bytes[1] as u16) << 8) | bytes[2] as u16;
+    Ok((bytes[3], offset, bytes[4..4 + len].to_vec()))
+}
+
+/// Parses Intel HEX text into `(address, bytes)` runs, one per data record.
+pub fn parse(text: &str) -> Result<Vec<(u32, Vec<u8>)>, String> {
+    let mut segments = Vec::new();
+    let mut base: u32 = 0;
+    for (lineno, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (kind, offset, data) = try!(parse_record(line)
+            .map_err(|e| format!("line {}: {}", lineno + 1, e)));
+        match kind {
+            DATA => segments.push((base + offset as u32, data)),
+            END_OF_FILE => return Ok(segments),
+            EXTENDED_SEGMENT_ADDRESS if data.len() == 2 => {
+                base = (((data[0] as u32) << 8) | data[1] as u32) << 4;
+            }
+            EXTENDED_LINEAR_ADDRESS if data.len() == 2 => {
+                base = (((data[0] as u32) << 8) | data[1] as u32) << 16;
+            }
+            START_SEGMENT_ADDRESS | START_LINEAR_ADDRESS => {}
+            _ => return Err(format!("line {}: unsupported record type {:02X}", lineno + 1, kind)),
+        }
+    }
+    Err("missing end of file record".into())
+}
+
+pub fn read_file(path: &str) -> Result<Vec<(u32, Vec<u8>)>, Error> {
+    let mut f = try!(File::open(path).map_err(|ioerr| Error::io(path, ioerr)));
+    let mut s = String::new();
+    try!(f.read_to_string(&mut s).map_err(|ioerr| Error::io(path, ioerr)));
+    parse(&s).map_err(|e| Error::Firmware(format!("{}: {}", path, e)))
+}
+
+fn write_record(out: &mut String, kind: u8, offset: u16, data: &[u8]) {
+    let mut checksum = (data.len() as u8)
+        .wrapping_add((offset >> 8) as u8)
+        .wrapping_add(offset as u8)
+        .wrapping_add(kind);
+    write!(out, ":{:02X}{:04X}{:02X}", data.len(), offset, kind).unwrap();
+    for b in data {
+        write!(out, "{:02X}", b).unwrap();
+        checksum = checksum.wrapping_add(*b);
+    }
+    write!(out, "{:02X}\n", checksum.wrapping_neg()).unwrap();
+}
+
+/// Formats `(address, bytes)` runs as Intel HEX, using extended linear
+/// address records for everything above 64 KiB.
+pub fn format(segments: &[(u32, Vec<u8>)]) -> String {
+    let mut out = String::new();
+    let mut upper: Option<u16> = None;
+    for &(addr, ref bytes) in segments {
+        let mut pos = 0;
+        while pos < bytes.len() {
+            let a = addr + pos as u32;
+            if upper != Some((a >> 16) as u16) {
+                let u = (a >> 16) as u16;
+                write_record(&mut out, EXTENDED_LINEAR_ADDRESS, 0, &[(u >> 8) as u8, u as u8]);
+                upper = Some(u);
+            }
+            // Records must not wrap around at a 64 KiB boundary.
+            let to_boundary = 0x10000 - (a & 0xFFFF) as usize;
+            let len = *[BYTES_PER_RECORD, bytes.len() - pos, to_boundary].iter().min().unwrap();
+            write_record(&mut out, DATA, a as u16, &bytes[pos..pos + len]);
+            pos += len;
+        }
+    }
+    write_record(&mut out, END_OF_FILE, 0, &[]);
+    out
+}
+
+pub fn write_file(path: &str, segments: &[(u32, Vec<u8>)]) -> Result<(), Error> {
+    let mut f = try!(File::create(path).map_err(|ioerr| Error::io(path, ioerr)));
+    f.write_all(format(segments).as_bytes()).map_err(|ioerr| Error::io(path, ioerr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_rejects_non_ascii_records() {
+        // As long as a valid record, but with a two byte character
+        let result = parse(":0100000\u{e9}0FF\n");
+        assert_eq!(result, Err("line 1: not a hex record, it contains non-ASCII characters".into()));
+    }
+
+    #[test]
+    fn format_computes_checksums() {
+        let data = vec![0x21, 0x46, 0x01, 0x36, 0x01, 0x21, 0x47, 0x01,
+                        0x36, 0x00, 0x7E, 0xFE, 0x09, 0xD2, 0x19, 0x01];
+        assert_eq!(format(&[(0x0100, data)]),
+                   ":020000040000FA\n:10010000214601360121470136007EFE09D2190140\n:00000001FF\n");
+    }
+
+    #[test]
+    fn format_splits_records_at_64_kib_boundaries() {
+        let data = (0..16).collect::<Vec<u8>>();
+        let text = format(&[(0xFFF8, data.clone())]);
+        assert_eq!(text, concat!(":020000040000FA\n",
+                                 ":08FFF8000001020304050607E5\n",
+                                 ":020000040001F9\n",
+                                 ":0800000008090A0B0C0D0E0F9C\n",
+                                 ":00000001FF\n"));
+        assert_eq!(parse(&text), Ok(vec![(0xFFF8, data[..8].to_vec()), (0x10000, data[8..].to_vec())]));
+    }
+
+    #[test]
+    fn format_writes_an_extended_address_only_when_it_changes() {
+        let text = format(&[(0x0001_0010, vec![1, 2]), (0x0001_0020, vec![3])]);
+        assert_eq!(text, ":020000040001F9\n:020010000102EB\n:0100200003DC\n:00000001FF\n");
+    }
+
+    #[test]
+    fn parse_reads_data_after_an_extended_address() {
+        let text = ":020000040001F9\n:020010000102EB\n:00000001FF\n";
+        assert_eq!(parse(text), Ok(vec![(0x0001_0010, vec![1, 2])]));
+    }
+}
@@ -1,48 +1,123 @@
-#![feature(btree_append)]
-
 extern crate rustc_serialize;
 extern crate docopt;
 extern crate toml;
+extern crate toml_edit;
 extern crate curl;
 extern crate yaml_rust;
 extern crate regex;
+#[cfg(target_os = "linux")]
+extern crate rusb;
+#[cfg(not(target_os = "linux"))]
+extern crate hidapi;
+extern crate libc;
+
+mod artifacts;
+mod boards;
+mod cache;
+mod config;
+mod diff;
+mod doctor;
+mod elf;
+mod error;
+mod halfkay;
+#[cfg(not(target_os = "linux"))]
+mod hid;
+mod ihex;
+mod merge;
+#[cfg(unix)]
+mod monitor;
+#[cfg(unix)]
+mod serial;
+mod size;
+mod templates;
+mod toolchain;
+mod tools;
+#[cfg(target_os = "linux")]
+mod usb;
 
-use regex::Regex;
 use docopt::Docopt;
-use std::process::{self, ExitStatus, Command};
-use std::fs::{File, DirBuilder};
-use std::io::Read;
-use std::io::Write;
+use std::process::{self, ExitStatus, Command, Stdio};
+use std::fs::{self, File, DirBuilder};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::io::{self, BufRead, BufReader, Read, Write};
 use yaml_rust::{YamlLoader};
 use yaml_rust::yaml::Yaml;
 use curl::easy::Easy;
+use error::Error;
+use toolchain::Toolchain;
 
 const USAGE: &'static str = "
 Teensy in one command.
 
 Usage:
-  cargo teensy upload [options]
-  cargo teensy new [--ignore-version] <name>
+  cargo teensy upload [options] [-- <cargo-args>...]
+  cargo teensy run [options] [-- <cargo-args>...]
+  cargo teensy build [options] [-- <cargo-args>...]
+  cargo teensy size [options] [-- <cargo-args>...]
+  cargo teensy new [options] <path>
+  cargo teensy init [options] [<path>]
+  cargo teensy doctor [options]
+  cargo teensy monitor [options]
   cargo teensy (-h | --help)
   cargo teensy --version
 
+Settings in [package.metadata.teensy] of Cargo.toml are used for options
+that are not given on the command line. Arguments after -- are passed on to
+cargo build.
+
 Options:
-  -r --hard-reboot     teensy_loader_cli: Use hard reboot if device not online
-  -s --soft-reboot     teensy_loader_cli: Use soft reboot if device not online (Teensy3.x only)
-  -n --no-reboot       teensy_loader_cli: No reboot after programming
-  --ignore-version     Do not stop if rustc versions do not match
+  -b --board=<board>   Teensy model: teensy30, teensy31 (default), teensy32,
+                       teensy35, teensy36 or teensylc
+  -r --hard-reboot     Use hard reboot if device not online (needs a rebootor)
+  -s --soft-reboot     Use soft reboot if device not online (Teensy3.x only)
+  -n --no-reboot       No reboot after programming
+  --size               upload, run: Print the memory usage after uploading
+  -o --out=<dir>       build: Copy the ELF and HEX files into <dir>
+  --bin=<name>         Build and upload the binary <name>
+  --debug              Use the dev profile instead of --release
+  --profile=<name>     Use the cargo profile <name> instead of --release
+  --example=<name>     Build and upload the example <name>
+  --flash-budget=<pc>  size: Fail if more than <pc> percent of the flash is used
+  --ram-budget=<pc>    size: Fail if more than <pc> percent of the RAM is used
+  --ignore-version     new, init: Do not stop if rustc versions do not match
+  -y --yes             new, init: Install the required toolchain and
+                       overwrite files without asking
+  --name=<crate>       new: Name the crate <crate> instead of after <path>
+  --vcs=<vcs>          new: Version control for cargo new: git, hg, pijul,
+                       fossil or none
+  -t --template=<tpl>  new, init: Start from template <tpl>: blink (default),
+                       usb-serial, minimal, library, cortex-m-rt, a template
+                       of the user template directory, a directory or a git URL
+  --offline            Check the rust version against the cached required
+                       version only, skip the check if there is none
+  --version-source=<src>  URL or file of the .travis.yml with the required
+                       rust version
+                       [default: https://raw.githubusercontent.com/hackndev/zinc/master/.travis.yml]
+  --port=<dev>         monitor, run: Serial port to open instead of the first
+                       Teensy found, e.g. a pseudo-terminal for testing
+  --baud=<rate>        monitor, run: Baud rate of the serial port
+                       [default: 115200]
+  --eol=<eol>          monitor, run: Line ending sent after each line of input:
+                       lf, cr or crlf
+                       [default: lf]
+  --echo               monitor, run: Show the lines sent to the board
+  --timestamps         monitor, run: Prefix received lines with the seconds
+                       since the start
+  --dry-run            new, init, build, upload, run: Show the commands and the
+                       file changes without running or writing anything
   -v --verbose         Show commands before executing
   -h --help            Show this screen.
   --version            Show version.
 ";
 
-const ABIJSON: &'static [u8] = br#"{
+const ABIJSON: &'static str = r#"{
     "arch": "arm",
-    "cpu": "cortex-m4",
+    "cpu": "{cpu}",
     "data-layout": "e-m:e-p:32:32-i64:64-v128:64:128-a:0:32-n32-S64",
     "disable-redzone": true,
     "executables": true,
-    "llvm-target": "thumbv7em-none-eabi",
+    "llvm-target": "{target}",
     "morestack": false,
     "os": "none",
     "relocation-model": "static",
@@ -50,7 +125,7 @@ const ABIJSON: &'static [u8] = br#"{
     "target-pointer-width": "32",
     "no-compiler-rt": true,
     "pre-link-args": [
-        "-mcpu=cortex-m4", "-mthumb",
+        "-mcpu={cpu}", "-mthumb",
         "-Tlayout.ld"
     ],
     "post-link-args": [
@@ -59,78 +134,36 @@ const ABIJSON: &'static [u8] = br#"{
 }
 "#;
 
-const EXAMPLEMAIN: &'static [u8] = br#"
-#![feature(plugin, start)]
-#![no_std]
-#![plugin(macro_zinc)]
-
-extern crate zinc;
-
-use core::option::Option::Some;
-
-use zinc::hal::cortex_m4::systick;
-use zinc::hal::k20::{pin, watchdog};
-use zinc::hal::pin::Gpio;
-
-/// Wait the given number of SysTick ticks
-pub fn wait(ticks: u32) {
-  let mut n = ticks;
-  // Reset the tick flag
-  systick::tick();
-  loop {
-    if systick::tick() {
-      n -= 1;
-      if n == 0 {
-        break;
-      }
-    }
-  }
-}
-
-#[zinc_main]
-pub fn main() {
-  zinc::hal::mem_init::init_stack();
-  zinc::hal::mem_init::init_data();
-  watchdog::init(watchdog::State::Disabled);
-
-  // Pins for MC HCK (http://www.mchck.org/)
-  let led1 = pin::Pin::new(pin::Port::PortC, 5, pin::Function::Gpio, Some(zinc::hal::pin::Out));
-
-  systick::setup(systick::ten_ms().unwrap_or(480000));
-  systick::enable();
-  loop {
-    led1.set_high();
-    wait(10);
-    led1.set_low();
-    wait(10);
-  }
-}
+const RUSTTOOLCHAIN: &'static str = r#"[toolchain]
+channel = "{channel}"
 "#;
 
-const MANIFESTADD: &'static str = r#"
-[features]
-default = ["mcu_k20"]
-mcu_k20 = ["zinc/mcu_k20"] # also enables the mcu_k20 feature in the zinc crate
+/// Appended to RUSTTOOLCHAIN for a target rustup can install.
+const RUSTTOOLCHAIN_TARGETS: &'static str = r#"targets = ["{target}"]
+"#;
 
-[dependencies]
-rust-libcore = "*"
+/// How long `cargo teensy run` retries a serial port that is busy or not
+/// accessible yet after the upload.
+const RUN_GRACE_SECS: u64 = 5;
 
-[dependencies.zinc]
-git = "https://github.com/hackndev/zinc.git"
-branch = "master"
+/// Sections that are not programmed into flash.
+const EXCLUDED_SECTIONS: &'static [&'static str] = &[".eeprom"];
 
-[dependencies.macro_zinc]
-git = "https://github.com/hackndev/zinc.git"
-branch = "master"
-path = "macro_zinc"
+/// About the Cargo.toml that `cargo new` writes, to show the changes to it
+/// with `--dry-run`.
+const CARGONEW: &'static str = r#"[package]
+name = "{name}"
+version = "0.1.0"
+edition = "2021"
 
+[dependencies]
 "#;
 
-const CARGOCONFIG: &'static [u8] = br#"
+const CARGOCONFIG: &'static str = r#"
 [build]
-target = "thumbv7em-none-eabi"
+target = "{target}"
 
-[target.thumbv7em-none-eabi]
+[target.{target}]
 linker = "arm-none-eabi-gcc"
 ar = "arm-none-eabi-ar"
 "#;
@@ -143,210 +176,1241 @@ struct Args {
     flag_hard_reboot: bool,
     flag_no_reboot: bool,
     flag_verbose: bool,
+    flag_dry_run: bool,
     flag_ignore_version: bool,
+    flag_offline: bool,
+    flag_yes: bool,
+    flag_template: Option<String>,
+    flag_name: Option<String>,
+    flag_vcs: Option<String>,
+    flag_version_source: String,
+    flag_board: Option<String>,
+    flag_size: bool,
+    flag_out: Option<String>,
+    flag_bin: Option<String>,
+    flag_debug: bool,
+    flag_profile: Option<String>,
+    flag_example: Option<String>,
+    flag_flash_budget: Option<u32>,
+    flag_ram_budget: Option<u32>,
+    flag_port: Option<String>,
+    flag_baud: u32,
+    flag_eol: String,
+    flag_echo: bool,
+    flag_timestamps: bool,
     cmd_upload: bool,
+    cmd_run: bool,
+    cmd_build: bool,
+    cmd_size: bool,
     cmd_new: bool,
-    arg_name: String,
+    cmd_init: bool,
+    cmd_doctor: bool,
+    cmd_monitor: bool,
+    arg_path: Option<String>,
+    arg_cargo_args: Vec<String>,
+}
+
+/// The values of the template placeholders for the given board.
+fn placeholders(board : &boards::Board) -> Vec<(&'static str, String)> {
+    vec![("cpu", board.cpu.into()),
+         ("target", board.target.into()),
+         ("feature", board.feature.into()),
+         ("board", board.name.into()),
+         ("description", board.description.into()),
+         ("led_pin", board.led_pin.to_string()),
+         ("led_port", board.led_port.into()),
+         ("led_bit", board.led_bit.to_string()),
+         ("flash_size", board.flash_size.to_string()),
+         ("ram_start", format!("0x{:08X}", board.ram_start)),
+         ("ram_size", board.ram_size.to_string())]
+}
+
+/// Replaces the `{key}` placeholders in `template` for the given board.
+fn fill(template : &str, board : &boards::Board) -> String {
+    templates::substitute(template, &placeholders(board))
+}
+
+/// Like `fill`, and also replaces `{name}` with the project name.
+fn fill_named(template : &str, board : &boards::Board, name : &str) -> String {
+    let mut values = placeholders(board);
+    values.push(("name", name.into()));
+    templates::substitute(template, &values)
+}
+
+/// The command line flags merged over the `[package.metadata.teensy]` settings.
+struct Settings {
+    board: &'static boards::Board,
+    reboot: halfkay::Reboot,
+    boot: bool,
+    features: Vec<String>,
+    build_args: Vec<String>,
+    profile: String,
+    cargo: String,
+    linker: Option<String>,
+    flash_budget: u32,
+    ram_budget: u32,
+}
+
+fn settings(args : &Args, config : &config::Config) -> Result<Settings, Error> {
+    let board = try!(boards::find(args.flag_board.as_ref().or(config.board.as_ref())
+                                  .map_or(boards::DEFAULT_BOARD, |b| &b[..]))
+                     .map_err(Error::Usage));
+    let reboot = if args.flag_hard_reboot {
+        halfkay::Reboot::Hard
+    } else if args.flag_soft_reboot {
+        halfkay::Reboot::Soft
+    } else {
+        config.reboot.unwrap_or(halfkay::Reboot::None)
+    };
+    // Templates without a HAL, like a library, need not declare the feature.
+    let mut features = Vec::new();
+    if config.declared_features.iter().any(|f| f == board.feature) {
+        features.push(board.feature.to_string());
+    }
+    features.extend(config.features.iter().cloned());
+    Ok(Settings {
+        board: board,
+        reboot: reboot,
+        boot: !(args.flag_no_reboot || config.no_reboot.unwrap_or(false)),
+        features: features,
+        build_args: config.build_args.iter().chain(args.arg_cargo_args.iter()).cloned().collect(),
+        profile: try!(profile(args, config)),
+        cargo: config.cargo.clone().unwrap_or("cargo".into()),
+        linker: config.linker.clone(),
+        flash_budget: args.flag_flash_budget.or(config.flash_budget).unwrap_or(100),
+        ram_budget: args.flag_ram_budget.or(config.ram_budget).unwrap_or(100),
+    })
+}
+
+fn profile(args : &Args, config : &config::Config) -> Result<String, Error> {
+    match (args.flag_debug, &args.flag_profile) {
+        (true, &Some(_)) => Err(Error::Usage("Use either --debug or --profile, not both".into())),
+        (true, &None) => Ok("dev".into()),
+        (false, &Some(ref profile)) => Ok(profile.clone()),
+        (false, &None) => Ok(config.profile.clone().unwrap_or("release".into())),
+    }
+}
+
+/// The cargo arguments that select a build profile.
+fn profile_args(profile : &str) -> Vec<String> {
+    match profile {
+        "release" => vec!["--release".into()],
+        "dev" | "debug" => vec![],
+        _ => vec!["--profile".into(), profile.into()],
+    }
 }
 
-fn execute(mut command : Command, args: &Args) -> (ExitStatus, String) {
+/// The error for a program that could not be started.
+fn spawn_error(program : &str, err : io::Error) -> Error {
+    if err.kind() == io::ErrorKind::NotFound {
+        Error::MissingTool(program.into())
+    } else {
+        Error::io(program, err)
+    }
+}
+
+#[cfg(unix)]
+fn termination_signal(exit_status : &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    exit_status.signal()
+}
+
+#[cfg(not(unix))]
+fn termination_signal(_ : &ExitStatus) -> Option<i32> {
+    None
+}
+
+fn check_status(exit_status : ExitStatus, cmd_str : String) -> Result<(), Error> {
+    if exit_status.success() {
+        Ok(())
+    } else if let Some(signal) = termination_signal(&exit_status) {
+        Err(Error::Killed(cmd_str, signal))
+    } else {
+        Err(Error::Failed(cmd_str, exit_status.code()))
+    }
+}
+
+/// Runs `command`, or with `--dry-run` only shows it.
+fn execute(command : Command, program : &str, args: &Args) -> Result<(), Error> {
+    if args.flag_dry_run {
+        println!("Would run: {:?}", command);
+        return Ok(());
+    }
+    run_command(command, program, args.flag_verbose)
+}
+
+fn run_command(mut command : Command, program : &str, verbose : bool) -> Result<(), Error> {
     let cmd_str = format!("{:?}", command);
-    if args.flag_verbose {
+    if verbose {
         println!(">> {}", cmd_str);
     }
-    let mut child = command.spawn().unwrap_or_else(|e| panic!("{}", e));
-    let exit_status = child.wait().unwrap_or_else(|e| panic!("{}", e));
-    (exit_status, cmd_str)
+    let mut child = try!(command.spawn().map_err(|e| spawn_error(program, e)));
+    let exit_status = try!(child.wait().map_err(|e| Error::io(program, e)));
+    check_status(exit_status, cmd_str)
 }
 
-fn manifest() -> Result<toml::Table, String> {
-    let mut f = try!(File::open("Cargo.toml").map_err(|ioerr| format!("{:?}", ioerr)));
+/// The `Error::Manifest` message when there is no Cargo.toml.
+const MANIFEST_NOT_FOUND: &'static str = "not found in the current directory";
+
+fn manifest() -> Result<toml::Table, Error> {
+    let mut f = try!(File::open("Cargo.toml").map_err(|ioerr| {
+        if ioerr.kind() == io::ErrorKind::NotFound {
+            Error::Manifest(MANIFEST_NOT_FOUND.into())
+        } else {
+            Error::io("Cargo.toml", ioerr)
+        }
+    }));
     let mut s = String::new();
-    try!(f.read_to_string(&mut s).map_err(|ioerr| format!("{:?}", ioerr)));
+    try!(f.read_to_string(&mut s).map_err(|ioerr| Error::io("Cargo.toml", ioerr)));
     let mut parser = toml::Parser::new(&s);
-    parser.parse().ok_or("Could not parse Cargo.toml".into())
+    match parser.parse() {
+        Some(table) => Ok(table),
+        None => {
+            let errors = parser.errors.iter().map(|e| {
+                let (line, col) = parser.to_linecol(e.lo);
+                format!("line {}, column {}: {}", line + 1, col + 1, e.desc)
+            }).collect::<Vec<_>>();
+            Err(Error::Manifest(errors.join("; ")))
+        }
+    }
 }
 
-fn binname(manifest : &toml::Table) -> String {
-    manifest.get("package").unwrap().as_table().unwrap()
-        .get("name").unwrap().as_str().unwrap().into()
+fn binname(manifest : &toml::Table) -> Result<String, Error> {
+    toml::Value::Table(manifest.clone()).lookup("package.name")
+        .and_then(|name| name.as_str()).map(|name| name.into())
+        .ok_or(Error::Manifest("package.name is missing".into()))
 }
 
-fn build(args: &Args) -> (ExitStatus, String) {
-    let mut command = Command::new("cargo");
+fn build_command(args: &Args, settings : &Settings) -> Command {
+    let mut command = Command::new(&settings.cargo);
     command.arg("build")
         .arg("--verbose")
-        .arg("--release")
-        .arg("--target=thumbv7em-none-eabi")
-        .arg("--features").arg("mcu_k20");
-    execute(command, &args)
+        .arg("--message-format=json");
+    if let Some(ref bin) = args.flag_bin {
+        command.arg("--bin").arg(bin);
+    }
+    if let Some(ref example) = args.flag_example {
+        command.arg("--example").arg(example);
+    }
+    command.args(&profile_args(&settings.profile));
+    command.arg(&format!("--target={}", settings.board.target));
+    if !settings.features.is_empty() {
+        command.arg("--features").arg(settings.features.join(" "));
+    }
+    command.args(&settings.build_args);
+    if let Some(ref linker) = settings.linker {
+        let var = format!("CARGO_TARGET_{}_LINKER",
+                          settings.board.target.to_uppercase().replace("-", "_"));
+        command.env(var, linker);
+    }
+    command
+}
+
+/// Runs `cargo build` and collects the executables it reports.
+fn build(args: &Args, settings : &Settings) -> Result<Vec<artifacts::Artifact>, Error> {
+    let mut command = build_command(args, settings);
+    command.stdout(Stdio::piped());
+
+    let cmd_str = format!("{:?}", command);
+    if args.flag_verbose {
+        println!(">> {}", cmd_str);
+    }
+    let mut child = try!(command.spawn().map_err(|e| spawn_error(&settings.cargo, e)));
+    let mut built = Vec::new();
+    if let Some(stdout) = child.stdout.take() {
+        for line in BufReader::new(stdout).lines() {
+            let line = try!(line.map_err(|e| Error::io(&settings.cargo, e)));
+            match artifacts::parse(&line) {
+                Ok(artifacts::Message::Artifact(artifact)) => built.push(artifact),
+                Ok(artifacts::Message::Diagnostic(text)) => {
+                    let _ = write!(io::stderr(), "{}", text);
+                }
+                Ok(artifacts::Message::Other) => {}
+                // Build scripts may print to stdout as well
+                Err(_) => println!("{}", line),
+            }
+        }
+    }
+    let exit_status = try!(child.wait().map_err(|e| Error::io(&settings.cargo, e)));
+    try!(check_status(exit_status, cmd_str));
+    Ok(built)
 }
 
-fn make_hex(args: &Args, binname : &str) -> ((ExitStatus, String), String) {
-    let hexfile = format!("target/thumbv7em-none-eabi/release/{}.hex", binname);
-    let mut command = Command::new("arm-none-eabi-objcopy");
-    command.arg("-O").arg("ihex")
-        .arg("-R").arg(".eeprom")
-        .arg(&format!("target/thumbv7em-none-eabi/release/{}", binname))
-        .arg(&hexfile);
-    (execute(command, &args) , hexfile)
+/// The `--bin` or `--example` given on the command line, as `(kind, name)`.
+fn wanted_target(args : &Args) -> Result<Option<(&'static str, &str)>, Error> {
+    match (&args.flag_bin, &args.flag_example) {
+        (&Some(_), &Some(_)) => Err(Error::Usage("Use either --bin or --example, not both".into())),
+        (&Some(ref bin), &None) => Ok(Some(("bin", bin))),
+        (&None, &Some(ref example)) => Ok(Some(("example", example))),
+        (&None, &None) => Ok(None),
+    }
+}
+
+/// Lists the binaries and examples of the project, for error messages.
+fn available_targets(settings : &Settings) -> Result<String, Error> {
+    let output = try!(Command::new(&settings.cargo)
+        .arg("metadata").arg("--no-deps").arg("--format-version").arg("1")
+        .output()
+        .map_err(|e| spawn_error(&settings.cargo, e)));
+    let targets = try!(artifacts::targets(&String::from_utf8_lossy(&output.stdout))
+                       .map_err(|e| Error::Manifest(format!("cargo metadata: {}", e))));
+    Ok(targets.iter().map(|&(ref kind, ref name)| format!("  --{} {}", kind, name))
+       .collect::<Vec<_>>().join("\n"))
 }
 
-fn upload(args: &Args, hexfile : &str) -> (ExitStatus, String) {
-    let mut command = Command::new("teensy_loader_cli");
-    command.arg("-w")
-        .arg("--mcu").arg("mk20dx256");
-    if args.flag_no_reboot {
-        command.arg("-n");
+/// Where cargo puts the executable, for `--dry-run`, which does not build
+/// it. A `target-dir` in the cargo configuration is not taken into account.
+fn expected_elf(args : &Args, settings : &Settings, binname : &str) -> String {
+    let mut path = std::env::var_os("CARGO_TARGET_DIR").map_or(PathBuf::from("target"),
+                                                                PathBuf::from);
+    path.push(settings.board.target);
+    path.push(match &settings.profile[..] {
+        "dev" | "debug" => "debug",
+        profile => profile,
+    });
+    if let Some(ref example) = args.flag_example {
+        path.push("examples");
+        path.push(example);
+    } else {
+        path.push(args.flag_bin.as_ref().map_or(binname, |bin| &bin[..]));
     }
-    if args.flag_hard_reboot {
-        command.arg("-r");
+    path.to_string_lossy().into_owned()
+}
+
+fn make_hex(args: &Args, elffile : &str, exclude : &[&str]) -> Result<String, Error> {
+    let hexfile = format!("{}.hex", elffile);
+    if args.flag_dry_run {
+        println!("Would convert {} to {} (without {})", elffile, hexfile, exclude.join(", "));
+        return Ok(hexfile);
     }
-    if args.flag_soft_reboot {
-        command.arg("-s");
+    if args.flag_verbose {
+        println!(">> {} -> {} (without {})", elffile, hexfile, exclude.join(", "));
     }
-    command.arg(&hexfile);
-    execute(command, &args)
+    let elf = try!(elf::read_file(elffile));
+    let segments = try!(elf.load_image(exclude)
+                        .map_err(|e| Error::Firmware(format!("{}: {}", elffile, e))));
+    try!(ihex::write_file(&hexfile, &segments));
+    Ok(hexfile)
 }
 
-fn exit_on_fail(result : (ExitStatus, String)) {
-    if result.0.success() {
-        return;
-    } else if let Some(code) = result.0.code() {
-        println!("Failed command: {}", result.1);
-        process::exit(code);
+fn upload(args: &Args, settings : &Settings, hexfile : &str) -> Result<(), Error> {
+    let board = settings.board;
+    let mcu = try!(halfkay::mcu(board.loader_mcu)
+        .ok_or(Error::Usage(format!("No uploader support for {}", board.loader_mcu))));
+    if args.flag_dry_run {
+        let reboot = match settings.reboot {
+            halfkay::Reboot::None => "press the button",
+            halfkay::Reboot::Soft => "soft reboot",
+            halfkay::Reboot::Hard => "hard reboot with the rebootor",
+        };
+        let boot = if settings.boot { "start it" } else { "leave it in the bootloader" };
+        println!("Would program {} into the {} ({}) over USB with HalfKay: wait for the \
+                  bootloader ({}), write {} byte blocks, then {}",
+                 hexfile, board.description, mcu.name, reboot, mcu.block_size, boot);
+        return Ok(());
     }
+    let segments = try!(ihex::read_file(hexfile));
+    let image = try!(halfkay::Image::new(&segments, mcu.code_size).map_err(Error::Firmware));
+    let options = halfkay::Options {
+        wait: true,
+        reboot: settings.reboot,
+        boot: settings.boot,
+        verbose: args.flag_verbose,
+    };
+    #[cfg(target_os = "linux")]
+    let mut transport = usb::UsbTransport::new();
+    #[cfg(not(target_os = "linux"))]
+    let mut transport = hid::HidTransport::new();
+    let blocks = try!(halfkay::program(&mut transport, &mcu, &image, &options)
+                      .map_err(Error::Device));
+    if args.flag_verbose {
+        println!("Programmed {} blocks of {} bytes", blocks, mcu.block_size);
+    }
+    Ok(())
+}
+
+/// Copies `files` into `dir`, returning the new paths.
+fn copy_artifacts(args : &Args, dir : &str, files : &[&str]) -> Result<Vec<String>, Error> {
+    if !args.flag_dry_run {
+        try!(DirBuilder::new().recursive(true).create(dir)
+             .map_err(|ioerr| Error::io(dir, ioerr)));
+    }
+    let mut copies = Vec::new();
+    for file in files {
+        let dest = Path::new(dir).join(Path::new(file).file_name().unwrap_or_default());
+        if args.flag_dry_run {
+            println!("Would copy {} to {}", file, dest.display());
+        } else {
+            try!(fs::copy(file, &dest).map_err(|ioerr| Error::io(file, ioerr)));
+        }
+        copies.push(dest.to_string_lossy().into_owned());
+    }
+    Ok(copies)
+}
+
+fn memory_usage(elffile : &str) -> Result<size::Usage, Error> {
+    let elf = try!(elf::read_file(elffile));
+    Ok(size::Usage::from_elf(&elf, EXCLUDED_SECTIONS))
 }
 
-fn cargo_new(args : &Args) -> (ExitStatus, String) {
+fn cargo_new(args : &Args, path : &Path, name : &str, template : &templates::Template)
+             -> Result<(), Error> {
     let mut command = Command::new("cargo");
     command.arg("new")
-        .arg(&args.arg_name)
-        .arg("--bin");
-    execute(command, &args)
+        .arg(path)
+        .arg("--name").arg(name)
+        .arg(match template.kind {
+            templates::Kind::Bin => "--bin",
+            templates::Kind::Lib => "--lib",
+        });
+    if let Some(ref vcs) = args.flag_vcs {
+        command.arg("--vcs").arg(vcs);
+    }
+    execute(command, "cargo", &args)
+}
+
+/// Finds the `--template`, cloning it first if it is a git URL.
+fn template(args : &Args) -> Result<templates::Template, Error> {
+    let name = args.flag_template.as_ref().map_or(templates::DEFAULT_TEMPLATE, |t| &t[..]);
+    if !templates::is_git_url(name) {
+        return templates::find(name);
+    }
+    if args.flag_dry_run {
+        return Err(Error::Usage(format!("--dry-run does not clone git templates. Clone it \
+                                         with `git clone --depth 1 {} <dir>` and pass the \
+                                         directory to --template instead.", name)));
+    }
+    let dir = std::env::temp_dir().join(format!("cargo-teensy-template-{}", process::id()));
+    let mut command = Command::new("git");
+    command.arg("clone").arg("--depth").arg("1").arg(name).arg(&dir);
+    let template = run_command(command, "git", args.flag_verbose)
+        .and_then(|_| templates::from_dir(&dir));
+    let _ = fs::remove_dir_all(&dir);
+    template
+}
+
+fn write_file(path : &str, contents : &[u8]) -> Result<(), Error> {
+    let mut f = try!(File::create(path).map_err(|ioerr| Error::io(path, ioerr)));
+    f.write_all(contents).map_err(|ioerr| Error::io(path, ioerr))
+}
+
+/// The contents of `path`, or `None` if it does not exist.
+fn read_file(path : &str) -> Result<Option<String>, Error> {
+    let mut s = String::new();
+    match File::open(path).and_then(|mut f| f.read_to_string(&mut s)) {
+        Ok(_) => Ok(Some(s)),
+        Err(ref ioerr) if ioerr.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(ioerr) => Err(Error::io(path, ioerr)),
+    }
+}
+
+/// Writes `contents` to `path`, creating its directory. With `--dry-run`
+/// the difference to `old`, the contents so far, is shown instead.
+fn put_file(args : &Args, path : &str, old : Option<&str>, contents : &str)
+            -> Result<(), Error> {
+    if args.flag_dry_run {
+        match old {
+            None => println!("Would create {}:\n{}", path, diff::unified(path, "", contents)),
+            Some(old) if old == contents => println!("Would leave {} unchanged", path),
+            Some(old) => println!("Would change {}:\n{}", path,
+                                  diff::unified(path, old, contents)),
+        }
+        return Ok(());
+    }
+    if let Some(dir) = Path::new(path).parent().filter(|d| !d.as_os_str().is_empty()) {
+        try!(DirBuilder::new().recursive(true).create(dir)
+             .map_err(|ioerr| Error::io(path, ioerr)));
+    }
+    write_file(path, contents.as_bytes())
+}
+
+/// Like `put_file`, but with `ask` a different existing file is only
+/// overwritten if the user agrees. Returns whether it was written.
+fn replace_file(args : &Args, path : &str, old : Option<&str>, contents : &str, ask : bool,
+                source : &str) -> Result<bool, Error> {
+    if ask && old.map_or(false, |old| old != contents) &&
+       !confirm(args, &format!("{} exists. Overwrite it with the one {}?", path, source)) {
+        return Ok(false);
+    }
+    try!(put_file(args, path, old, contents));
+    Ok(true)
+}
+
+/// `path` inside the project directory `dir`.
+fn project_path(dir : &Path, path : &str) -> String {
+    dir.join(path).to_string_lossy().into_owned()
+}
+
+/// Writes the target specification. Returns whether it was written, see `replace_file`.
+fn write_abi(args : &Args, dir : &Path, board : &boards::Board, ask : bool)
+             -> Result<bool, Error> {
+    let path = project_path(dir, &format!("{}.json", board.target));
+    let old = try!(read_file(&path));
+    replace_file(args, &path, old.as_ref().map(|s| &s[..]), &fill(ABIJSON, board), ask,
+                 &format!("for the {}", board.description))
+}
+
+/// Writes the files of `template`, except its cargo configuration, see
+/// `write_cargo_helper`. With `ask`, existing files are only overwritten if
+/// the user agrees. Returns the files that were kept.
+fn write_template(args : &Args, dir : &Path, template : &templates::Template, name : &str,
+                  board : &boards::Board, ask : bool) -> Result<Vec<String>, Error> {
+    let mut kept = Vec::new();
+    for &(ref file, ref contents) in &template.files {
+        if templates::is_cargo_config(file) {
+            continue;
+        }
+        let path = project_path(dir, file);
+        let old = try!(read_file(&path));
+        let contents = fill_named(contents, board, name);
+        if !try!(replace_file(args, &path, old.as_ref().map(|s| &s[..]), &contents, ask,
+                              &format!("of template {}", template.name))) {
+            kept.push(file.clone());
+        }
+    }
+    Ok(kept)
+}
+
+/// Adds the manifest additions of the template and the board setting to
+/// Cargo.toml. Existing settings are kept, and reported if the template
+/// wants them different. With `--dry-run` a project that `cargo new` did
+/// not create yet starts from what it would write.
+fn update_manifest(args : &Args, dir : &Path, name : &str, template : &templates::Template,
+                   board : &boards::Board) -> Result<(), Error> {
+    let path = project_path(dir, "Cargo.toml");
+    let s = match try!(read_file(&path)) {
+        Some(s) => s,
+        None if args.flag_dry_run => CARGONEW.replace("{name}", name),
+        None => return Err(Error::Manifest(MANIFEST_NOT_FOUND.into())),
+    };
+    let mut manifest = try!(merge::parse(&s).map_err(Error::Manifest));
+    let additions = fill_named(&template.manifest, board, name);
+    let addition = try!(merge::parse(&additions).map_err(|e| {
+        Error::Usage(format!("The manifest additions of template {} are not valid TOML: {}",
+                             template.name, e))
+    }));
+
+    for conflict in merge::merge(&mut manifest, &addition) {
+        let _ = writeln!(io::stderr(), "Warning: Cargo.toml: Kept {} = {}, template {} wants {}",
+                         conflict.key, conflict.existing, template.name, conflict.wanted);
+    }
+    merge::set(&mut manifest, &["package", "metadata", "teensy", "board"], board.name.into());
+
+    put_file(args, &path, Some(&s), &manifest.to_string())
 }
 
+/// The cargo configuration of the project: the legacy `.cargo/config` if it
+/// exists, as cargo then ignores `.cargo/config.toml`, else `.cargo/config.toml`.
+fn cargo_config_path(dir : &Path) -> String {
+    let path = project_path(dir, ".cargo/config");
+    if Path::new(&path).exists() {
+        path
+    } else {
+        project_path(dir, ".cargo/config.toml")
+    }
+}
 
+/// Adds the cargo configuration of the template, or else the target and the
+/// tools for the board, to the cargo configuration. Existing settings are
+/// kept, and reported if they differ.
+fn write_cargo_helper(args : &Args, dir : &Path, name : &str, template : &templates::Template,
+                      board : &boards::Board) -> Result<(), Error> {
+    let path = cargo_config_path(dir);
+    let old = try!(read_file(&path));
+    let s = old.clone().unwrap_or_default();
+    let mut config = try!(merge::parse(&s).map_err(|e| Error::Usage(format!("{}: {}", path, e))));
+    let addition = match templates::cargo_config(template) {
+        Some(contents) => try!(merge::parse(&fill_named(contents, board, name)).map_err(|e| {
+            Error::Usage(format!("The cargo configuration of template {} is not valid TOML: {}",
+                                 template.name, e))
+        })),
+        None => try!(merge::parse(&fill(CARGOCONFIG, board))
+                     .map_err(|e| Error::Usage(format!("CARGOCONFIG: {}", e)))),
+    };
+    for conflict in merge::merge(&mut config, &addition) {
+        let _ = writeln!(io::stderr(), "Warning: {}: Kept {} = {}, the {} needs {}",
+                         path, conflict.key, conflict.existing, board.description, conflict.wanted);
+    }
+    put_file(args, &path, old.as_ref().map(|s| &s[..]), &config.to_string())
+}
 
-fn write_abi(_ : &Args) {
-    let mut f = File::create("thumbv7em-none-eabi.json").unwrap();
-    f.write_all(ABIJSON).unwrap();
+/// Whether rustup installs the standard library for the project's target.
+/// The generated target specification is unknown to rustup, zinc builds its
+/// core library with rust-libcore instead.
+fn rustup_target_needed(template : &templates::Template) -> bool {
+    !template.target_spec
 }
 
-fn write_main(_ : &Args) {
-    let mut f = File::create("src/main.rs").unwrap();
-    f.write_all(EXAMPLEMAIN).unwrap();
+/// Pins `required` in rust-toolchain.toml. Returns whether it was written,
+/// see `replace_file`.
+fn write_rust_toolchain(args : &Args, dir : &Path, required : &Toolchain,
+                        template : &templates::Template, board : &boards::Board, ask : bool)
+                        -> Result<bool, Error> {
+    let mut contents = RUSTTOOLCHAIN.replace("{channel}", &required.to_string());
+    if rustup_target_needed(template) {
+        contents.push_str(&fill(RUSTTOOLCHAIN_TARGETS, board));
+    }
+    let path = project_path(dir, "rust-toolchain.toml");
+    let old = try!(read_file(&path));
+    replace_file(args, &path, old.as_ref().map(|s| &s[..]), &contents, ask,
+                 &format!("for {}", required))
 }
 
-fn update_manifest(manifest : &mut toml::Table) {
-    
-    let mut parser = toml::Parser::new(MANIFESTADD);
-    let mut addition = parser.parse().unwrap();
+fn rustup_installed() -> bool {
+    Command::new("rustup").arg("--version").output().is_ok()
+}
 
-    manifest.append(&mut addition);
+/// Runs `rustup` with `args` and returns the first word of each line it prints.
+fn rustup_list(args : &[&str]) -> Vec<String> {
+    Command::new("rustup").args(args).output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).lines()
+             .filter_map(|l| l.split_whitespace().next()).map(|w| w.to_string()).collect())
+        .unwrap_or_default()
+}
 
-    let mut f = File::create("Cargo.toml").unwrap();
-    f.write_all(format!("{}", toml::Value::Table(manifest.clone())).as_bytes()).unwrap();
+/// The rustup commands that install what rust-toolchain.toml asks for and is missing.
+fn missing_rustup_commands(required : &Toolchain, template : &templates::Template,
+                           board : &boards::Board) -> Vec<Vec<String>> {
+    let toolchain = required.to_string();
+    let mut commands = Vec::new();
+    let installed = rustup_list(&["toolchain", "list"]).iter()
+        .any(|t| *t == toolchain || t.starts_with(&format!("{}-", toolchain)));
+    if !installed {
+        commands.push(vec!["toolchain".into(), "install".into(), toolchain.clone()]);
+    }
+    if !rustup_target_needed(template) {
+        return commands;
+    }
+    let targets = rustup_list(&["target", "list", "--installed", "--toolchain", &toolchain]);
+    if !installed || !targets.iter().any(|t| t == board.target) {
+        commands.push(vec!["target".into(), "add".into(), "--toolchain".into(),
+                           toolchain.clone(), board.target.into()]);
+    }
+    commands
 }
 
-fn write_cargo_helper(_: &Args) {
-    DirBuilder::new().recursive(true).create(".cargo").unwrap();
-    let mut f = File::create(".cargo/config").unwrap();
-    f.write_all(CARGOCONFIG).unwrap();    
+/// Asks a yes/no question on the terminal. `--yes` answers it in advance,
+/// and `--dry-run` shows what would happen on yes.
+fn confirm(args : &Args, question : &str) -> bool {
+    if args.flag_yes {
+        return true;
+    }
+    if args.flag_dry_run {
+        println!("{} [y/N] (assuming yes for --dry-run)", question);
+        return true;
+    }
+    print!("{} [y/N] ", question);
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).is_ok() && answer.trim().to_lowercase().starts_with('y')
 }
 
-fn get_zinc_travis_yaml(_: &Args) -> String {
+/// Installs the toolchain of the new project with rustup, if the user agrees.
+fn install_toolchain(args : &Args, required : &Toolchain, template : &templates::Template,
+                     board : &boards::Board) -> Result<(), Error> {
+    if !rustup_installed() {
+        return Ok(());
+    }
+    let commands = missing_rustup_commands(required, template, board);
+    if commands.is_empty() {
+        return Ok(());
+    }
+    let lines = commands.iter().map(|c| format!("  rustup {}", c.join(" ")))
+        .collect::<Vec<_>>().join("\n");
+    println!("The project needs {} for {}, which is not fully installed:\n{}",
+             required, board.target, lines);
+    if !confirm(args, "Run these commands now?") {
+        println!("Skipped. Run them before building the project.");
+        return Ok(());
+    }
+    for command_args in commands {
+        let mut command = Command::new("rustup");
+        command.args(&command_args);
+        try!(execute(command, "rustup", &args));
+    }
+    Ok(())
+}
+
+fn download(url : &str) -> Result<String, Error> {
+    let network_error = |msg : String| Error::Network(url.into(), msg);
     let mut dst = Vec::new();
 
     {
         let mut easy = Easy::new();
-        easy.url("https://raw.githubusercontent.com/hackndev/zinc/master/.travis.yml").expect("not a url");
+        try!(easy.url(url).map_err(|e| network_error(format!("{}", e))));
+        try!(easy.follow_location(true).map_err(|e| network_error(format!("{}", e))));
+        try!(easy.timeout(Duration::from_secs(30)).map_err(|e| network_error(format!("{}", e))));
 
-        let mut transfer = easy.transfer();
-        transfer.write_function(|data| {
-            dst.extend_from_slice(data);
-            Ok(data.len())
-        }).unwrap();
-        transfer.perform().expect("transfer failed");
+        {
+            let mut transfer = easy.transfer();
+            try!(transfer.write_function(|data| {
+                dst.extend_from_slice(data);
+                Ok(data.len())
+            }).map_err(|e| network_error(format!("{}", e))));
+            try!(transfer.perform().map_err(|e| network_error(format!("{}", e))));
+        }
+        match try!(easy.response_code().map_err(|e| network_error(format!("{}", e)))) {
+            200 => {}
+            code => return Err(network_error(format!("HTTP status {}", code))),
+        }
     }
-    let travis = String::from_utf8(dst).unwrap();
+    String::from_utf8(dst).map_err(|_| network_error("not a text file".into()))
+}
 
-    let docs = YamlLoader::load_from_str(&travis).expect("Not a yaml file");
-    let doc = &docs[0]; // select the first document
-    let rustversionline = doc.as_hash().unwrap().get(&Yaml::String("rust".into())).unwrap().as_str().unwrap();
-    get_nightly_version(rustversionline).into()    
+/// The rust version in the `rust` key of a .travis.yml.
+fn travis_rust_version(travis : &str) -> Result<Toolchain, String> {
+    let docs = try!(YamlLoader::load_from_str(travis)
+                    .map_err(|e| format!("not a yaml file: {}", e)));
+    let rustversionline = try!(docs.get(0) // select the first document
+        .and_then(|doc| doc.as_hash())
+        .and_then(|doc| doc.get(&Yaml::String("rust".into())))
+        .and_then(|rust| rust.as_str())
+        .ok_or("no rust version found".to_string()));
+    Toolchain::parse(rustversionline)
 }
 
-fn rustc_version(_ : &Args) -> String {
-    let mut command = Command::new("rustup");
-    command.arg("show");
-    let output = command.output().expect("could not execute rustc --version");
-    let output = &String::from_utf8_lossy(&output.stdout);
-    let active = &output[output.find("active toolchain").unwrap()..];
-    get_nightly_version( &active ).into()
+fn is_url(source : &str) -> bool {
+    source.starts_with("http://") || source.starts_with("https://")
 }
 
-fn get_nightly_version(txt : &str) -> &str {
-    if txt.find("stable").is_some() {
-        return txt;
+/// The rust version required by the .travis.yml at `--version-source`.
+/// Downloads are cached, and `None` is returned if `--offline` is given and
+/// nothing is cached.
+fn get_zinc_travis_yaml(args : &Args) -> Result<Option<Toolchain>, Error> {
+    let source = &args.flag_version_source;
+    if !is_url(source) {
+        let mut travis = String::new();
+        try!(File::open(source).and_then(|mut f| f.read_to_string(&mut travis))
+             .map_err(|ioerr| Error::io(source, ioerr)));
+        return travis_rust_version(&travis).map(Some)
+            .map_err(|e| Error::Usage(format!("{}: {}", source, e)));
+    }
+
+    // An unreadable entry, e.g. from an older cargo-teensy, is not there.
+    let cached = cache::read(source).and_then(|entry| {
+        Toolchain::parse(&entry.version).ok().map(|version| (version, entry.fresh))
+    });
+    if args.flag_offline {
+        if cached.is_none() {
+            let _ = writeln!(io::stderr(), "Warning: No cached rust version for {}, skipping \
+                                            the version check.", source);
+        }
+        return Ok(cached.map(|(version, _)| version));
+    }
+    if let Some((ref version, true)) = cached {
+        return Ok(Some(version.clone()));
+    }
+
+    let downloaded = download(source).and_then(|travis| {
+        travis_rust_version(&travis).map_err(|e| Error::Network(source.clone(), e))
+    });
+    match (downloaded, cached) {
+        (Ok(version), _) => {
+            if args.flag_dry_run {
+                return Ok(Some(version));
+            }
+            if let Err(e) = cache::write(source, &version.to_string()) {
+                if args.flag_verbose {
+                    println!("Note: Cannot cache the rust version: {}", e);
+                }
+            }
+            Ok(Some(version))
+        }
+        (Err(e), Some((version, _))) => {
+            let _ = writeln!(io::stderr(), "Warning: {}\nUsing the cached rust version {}.",
+                             e, version);
+            Ok(Some(version))
+        }
+        (Err(e), None) => Err(e),
     }
-    let re = Regex::new(r".*(\d{4}-\d{2}-\d{2}).*").unwrap();
-    re.captures_iter(txt).next().unwrap().at(1).unwrap()
 }
 
-fn assert_rust_version(args : &Args) {
-    let rustversion = get_zinc_travis_yaml(&args);
-    let rustcinstalled = rustc_version(&args);
+/// The active toolchain according to rustup. If rustup is not installed or
+/// its toolchain name is not exact, like `nightly`, rustc is asked instead.
+fn rustc_version(_ : &Args) -> Result<Toolchain, Error> {
+    if let Ok(output) = Command::new("rustup").arg("show").arg("active-toolchain").output() {
+        let output = String::from_utf8_lossy(&output.stdout);
+        let name = output.split_whitespace().next().unwrap_or("");
+        if let Ok(toolchain) = Toolchain::parse(name) {
+            if toolchain.is_exact() {
+                return Ok(toolchain);
+            }
+        }
+    }
+    let output = try!(Command::new("rustc").arg("-vV").output()
+                      .map_err(|e| spawn_error("rustc", e)));
+    Toolchain::from_rustc_verbose_version(&String::from_utf8_lossy(&output.stdout))
+        .map_err(Error::UnknownRustVersion)
+}
 
+/// Checks the rust version for a new project and returns the required
+/// toolchain, if it is known. With rustup a mismatch is fine, as the
+/// rust-toolchain.toml of the project selects the required toolchain.
+fn assert_rust_version(args : &Args) -> Result<Option<Toolchain>, Error> {
+    let rustversion = match get_zinc_travis_yaml(&args) {
+        Ok(Some(rustversion)) => rustversion,
+        Ok(None) => return Ok(None),
+        Err(e) => {
+            if args.flag_ignore_version {
+                println!("Note: Cannot check the rust version: {}", e);
+                return Ok(None);
+            }
+            return Err(e);
+        }
+    };
+    let rustcinstalled = try!(rustc_version(&args));
 
-    if rustversion != rustcinstalled{
-        if args.flag_ignore_version {
-            println!("Note: Installed rust version {} does not match the version {} defined in\
-                      github.com/hackndev/zinc/master/.travis.yml.", rustcinstalled, rustversion);
+    if rustcinstalled.compare(&rustversion) != toolchain::Comparison::Same {
+        if rustup_installed() {
+            let mismatch = Error::RustVersion(rustcinstalled, rustversion.clone());
+            println!("Note: {} The project will use {} through rust-toolchain.toml.",
+                     mismatch.message(), rustversion);
+        } else if args.flag_ignore_version {
+            println!("Note: Installed rust version {} does not match the required version {}.",
+                     rustcinstalled, rustversion);
         } else {
-            println!("Error: Installed rust version {} does not match the version {} defined in \
-                      github.com/hackndev/zinc/master/.travis.yml.\n\
-                      Install with: rustup override set nightly-{}\n\
-                      Or use: cargo teensy new --ignore-version",
-                      rustcinstalled, rustversion, rustversion);
-            process::exit(-1);
+            return Err(Error::RustVersion(rustcinstalled, rustversion));
         }
+    }
+    Ok(Some(rustversion))
+}
 
+/// The rust-toolchain.toml of the project in the current directory, if it has one.
+fn rust_toolchain_file() -> Result<Option<config::ToolchainFile>, Error> {
+    let path = "rust-toolchain.toml";
+    match try!(read_file(path)) {
+        Some(s) => config::ToolchainFile::parse(&s).map(Some)
+            .map_err(|e| Error::Usage(format!("{}: {}", path, e))),
+        None => Ok(None),
     }
-    return;
 }
 
-fn main() {
-    let args: Args = Docopt::new(USAGE)
-                            .and_then(|d| { d.decode() })
-                            .unwrap_or_else(|e| e.exit());
+/// Checks the installed rust against the toolchain the project pins in
+/// rust-toolchain.toml, or against zinc's if it pins none.
+fn toolchain_check(args : &Args, file : &config::ToolchainFile) -> doctor::Check {
+    let installed = match rustc_version(&args) {
+        Ok(installed) => installed,
+        Err(e) => return doctor::Check::fail("toolchain", &e.message(), &e.fix()),
+    };
+    let pinned = match file.channel {
+        Some(ref channel) => match Toolchain::parse(channel) {
+            Ok(pinned) => Ok(Some(pinned)),
+            Err(e) => {
+                return doctor::Check::fail("toolchain", &format!("rust-toolchain.toml: {}", e),
+                                           "Set channel to a toolchain like stable or \
+                                            nightly-2016-05-24.");
+            }
+        },
+        None => get_zinc_travis_yaml(&args),
+    };
+    match pinned {
+        Ok(Some(ref required)) if installed.compare(required) == toolchain::Comparison::Same => {
+            doctor::Check::pass("toolchain", &installed.to_string())
+        }
+        Ok(None) => {
+            doctor::Check::pass("toolchain", &format!("{}, not checked (offline)", installed))
+        }
+        Ok(Some(required)) => {
+            let error = Error::RustVersion(installed, required);
+            doctor::Check::fail("toolchain", &error.message(), &error.fix())
+        }
+        Err(e) => {
+            let detail = format!("{}, required version unknown", installed);
+            doctor::Check::fail("toolchain", &detail, &format!("{}\n{}", e.message(), e.fix()))
+        }
+    }
+}
 
-    if args.cmd_upload {
-        let manifest = manifest().unwrap();
-        let binname = binname(&manifest);
+fn doctor(args : &Args) -> Result<(), Error> {
+    // Outside of a project the defaults are checked. A broken Cargo.toml is
+    // reported, and the defaults are checked as well.
+    let mut manifest_check = None;
+    let config = manifest()
+        .and_then(|manifest| config::Config::from_manifest(&manifest).map_err(Error::Manifest));
+    let config = match config {
+        Ok(config) => config,
+        Err(Error::Manifest(ref msg)) if msg == MANIFEST_NOT_FOUND => config::Config::default(),
+        Err(e) => {
+            let detail = match e {
+                Error::Manifest(msg) => msg,
+                e => e.message(),
+            };
+            manifest_check = Some(doctor::Check::fail("Cargo.toml", &detail,
+                                                      "Fix the manifest of the project."));
+            config::Config::default()
+        }
+    };
+    let settings = try!(settings(&args, &config));
+    let linker = settings.linker.clone().unwrap_or("arm-none-eabi-gcc".into());
+    let target_spec = Path::new(&format!("{}.json", settings.board.target)).exists();
+    // Projects with their own cargo configuration, like cortex-m-rt ones,
+    // link with the rust-lld of the built-in target instead of gcc.
+    let uses_gcc = target_spec || settings.linker.is_some() ||
+                   !Path::new(&cargo_config_path(Path::new(""))).exists();
 
-        exit_on_fail(build(&args));
+    let file = try!(rust_toolchain_file()).unwrap_or_default();
+    let channel = file.channel.as_ref().map(|c| &c[..]);
+    // The generated target specification is not a rustup target.
+    let target = if target_spec {
+        doctor::Check::pass(&format!("target {}", settings.board.target),
+                            "target specification in the project")
+    } else {
+        doctor::rustup_target(settings.board.target, channel)
+    };
 
+    let mut checks = manifest_check.into_iter().collect::<Vec<_>>();
+    checks.extend(vec![
+        doctor::tool("rustup"),
+        doctor::tool(&settings.cargo),
+        doctor::tool("rustc"),
+        toolchain_check(&args, &file),
+        target,
+    ]);
+    if uses_gcc {
+        checks.push(doctor::tool(&linker));
+        checks.push(doctor::tool("arm-none-eabi-ar"));
+        checks.push(doctor::newlib(&linker));
+    } else {
+        checks.push(doctor::Check::pass("linker", "rust-lld of the target"));
+    }
+    checks.push(doctor::rustup_components(channel, &file.components));
+    if cfg!(all(unix, not(target_os = "macos"))) {
+        checks.push(doctor::openssl_headers());
+    }
+    checks.push(doctor::usb_access());
+    println!("Checking for {}:", settings.board.description);
+    println!("{}", doctor::report(&checks));
+    match checks.iter().filter(|c| !c.ok).count() {
+        0 => Ok(()),
+        failed => Err(Error::Unhealthy(failed)),
+    }
+}
 
-        let (result, hexfile) = make_hex(&args, &binname);
-        exit_on_fail(result);
+/// Shows the serial output of the board. `first` runs once the monitor
+/// settings are known to be valid, like the upload of `cargo teensy run`.
+/// Errors opening the port are retried for `grace`, see `monitor::Options`.
+#[cfg(unix)]
+fn monitor<F>(args : &Args, grace : Duration, first : F) -> Result<(), Error>
+    where F : FnOnce() -> Result<(), Error> {
+    if !serial::BAUD_RATES.contains(&args.flag_baud) {
+        let rates = serial::BAUD_RATES.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+        return Err(Error::Usage(format!("Unsupported baud rate {}, use one of {}",
+                                        args.flag_baud, rates.join(", "))));
+    }
+    let eol = try!(monitor::eol(&args.flag_eol).ok_or(Error::Usage(
+        format!("Unknown line ending {}, use lf, cr or crlf", args.flag_eol))));
+    try!(first());
+    if args.flag_dry_run {
+        println!("Would show the serial output of the board until Ctrl-C");
+        return Ok(());
+    }
+    monitor::run(&monitor::Options {
+        port: args.flag_port.clone(),
+        baud: args.flag_baud,
+        eol: eol,
+        echo: args.flag_echo,
+        timestamps: args.flag_timestamps,
+        grace: grace,
+    })
+}
 
-        println!("UPLOAD (waiting for reset)");
-        exit_on_fail(upload(&args, &hexfile));
+#[cfg(not(unix))]
+fn monitor<F>(_ : &Args, _ : Duration, _ : F) -> Result<(), Error>
+    where F : FnOnce() -> Result<(), Error> {
+    Err(Error::Usage("The serial monitor is only available on Linux and macOS".into()))
+}
 
-        println!("Upload successful");
-    } else if args.cmd_new {
-        assert_rust_version(&args);
+/// The board, the template and the required toolchain of a new project.
+/// Without `--board` the board of the `[package.metadata.teensy]` settings is used.
+fn project_choices(args : &Args, config : &config::Config)
+                   -> Result<(&'static boards::Board, templates::Template, Option<Toolchain>), Error> {
+    let board = try!(boards::find(args.flag_board.as_ref().or(config.board.as_ref())
+                                  .map_or(boards::DEFAULT_BOARD, |b| &b[..]))
+                     .map_err(Error::Usage));
+    let template = try!(template(&args));
+    if !templates::supports(&template, board.name) {
+        return Err(Error::Usage(format!("Template {} does not support the {}, only {}. Try \
+                                         --template cortex-m-rt or minimal.", template.name,
+                                        board.description, template.boards.join(", "))));
+    }
+    let required = match template.toolchain {
+        Some(ref toolchain) => Some(try!(Toolchain::parse(toolchain).map_err(|e| {
+            Error::Usage(format!("Template {}: {}", template.name, e))
+        }))),
+        None => try!(assert_rust_version(&args)),
+    };
+    Ok((board, template, required))
+}
 
-        cargo_new(&args);
-        std::env::set_current_dir(&args.arg_name).unwrap();
+/// The files that `set_up_project` writes.
+fn project_files(template : &templates::Template, required : &Option<Toolchain>,
+                 board : &boards::Board) -> Vec<String> {
+    let mut files = Vec::new();
+    if template.target_spec {
+        files.push(format!("{}.json", board.target));
+    }
+    files.extend(template.files.iter().map(|&(ref path, _)| path.clone())
+                 .filter(|path| !templates::is_cargo_config(path)));
+    files.push(cargo_config_path(Path::new("")));
+    if required.is_some() {
+        files.push("rust-toolchain.toml".into());
+    }
+    files.push("Cargo.toml".into());
+    files
+}
+
+/// Sets up the crate in `dir` for the board. With `ask`, files of the
+/// template are only overwritten if the user agrees. Returns the files that
+/// were kept.
+fn set_up_project(args : &Args, dir : &Path, name : &str, board : &boards::Board,
+                  template : &templates::Template, required : &Option<Toolchain>, ask : bool)
+                  -> Result<Vec<String>, Error> {
+    let mut kept = Vec::new();
+    if template.target_spec && !try!(write_abi(&args, dir, board, ask)) {
+        kept.push(format!("{}.json", board.target));
+    }
+    kept.extend(try!(write_template(&args, dir, template, name, board, ask)));
+    try!(write_cargo_helper(&args, dir, name, template, board));
+    match *required {
+        Some(ref required) => {
+            if try!(write_rust_toolchain(&args, dir, required, template, board, ask)) {
+                try!(install_toolchain(&args, required, template, board));
+            } else {
+                // The kept file pins another toolchain, which is not ours to install
+                kept.push("rust-toolchain.toml".into());
+            }
+        }
+        None => println!("Note: The required rust version is unknown, \
+                          no rust-toolchain.toml written."),
+    }
+
+    try!(update_manifest(&args, dir, name, template, board));
+    Ok(kept)
+}
+
+/// Creates the project in a directory next to `<path>` and moves it into
+/// place only when everything succeeded, so that a failure leaves nothing behind.
+/// With `--dry-run` the files are shown as they would be in `<path>`.
+fn new(args : &Args) -> Result<(), Error> {
+    let path = args.arg_path.clone().unwrap_or_default();
+    let target = Path::new(&path);
+    if target.exists() {
+        return Err(Error::Usage(format!("{} already exists", path)));
+    }
+    let name = match (&args.flag_name, target.file_name()) {
+        (&Some(ref name), _) => name.clone(),
+        (&None, Some(name)) => name.to_string_lossy().into_owned(),
+        (&None, None) => return Err(Error::Usage(format!("Cannot name a crate after {}, \
+                                                          use --name", path))),
+    };
+    let (board, template, required) = try!(project_choices(&args, &config::Config::default()));
+    if args.flag_dry_run {
+        try!(cargo_new(&args, target, &name, &template));
+        try!(set_up_project(&args, target, &name, board, &template, &required, false));
+        println!("Dry run, nothing was written.");
+        return Ok(());
+    }
+
+    let parent = target.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let staging = parent.join(format!(".{}.cargo-teensy-{}", name, process::id()));
+    let cwd = try!(std::env::current_dir().map_err(|ioerr| Error::io(".", ioerr)));
+    let staging_name = staging.to_string_lossy().into_owned();
+    // cargo new adds the staging directory to the members of an enclosing workspace
+    let workspace = workspace_manifest(parent);
+
+    let result = cargo_new(&args, &staging, &name, &template)
+        .and_then(|_| std::env::set_current_dir(&staging)
+                  .map_err(|ioerr| Error::io(&staging_name, ioerr)))
+        .and_then(|_| set_up_project(&args, Path::new(""), &name, board, &template, &required,
+                                     false));
+    try!(std::env::set_current_dir(&cwd)
+         .map_err(|ioerr| Error::io(&cwd.to_string_lossy(), ioerr)));
+
+    let result = result
+        .and_then(|_| workspace.as_ref().map_or(Ok(()), |&(ref manifest, _)| {
+            rename_workspace_member(manifest, &staging, target)
+        }))
+        .and_then(|_| fs::rename(&staging, target).map_err(|ioerr| Error::io(&path, ioerr)));
+    if result.is_err() {
+        let _ = fs::remove_dir_all(&staging);
+        if let Some((ref manifest, ref original)) = workspace {
+            let manifest = manifest.to_string_lossy();
+            if read_file(&manifest).ok().and_then(|s| s).as_ref() != Some(original) {
+                let _ = write_file(&manifest, original.as_bytes());
+            }
+        }
+    }
+    result
+}
+
+/// The manifest of the workspace that `dir` is in, if any, and its contents.
+fn workspace_manifest(dir : &Path) -> Option<(PathBuf, String)> {
+    let dir = match dir.canonicalize() {
+        Ok(dir) => dir,
+        Err(_) => return None,
+    };
+    for ancestor in dir.ancestors() {
+        let path = ancestor.join("Cargo.toml");
+        if let Ok(Some(s)) = read_file(&path.to_string_lossy()) {
+            if merge::parse(&s).ok().map_or(false, |doc| doc.get("workspace").is_some()) {
+                return Some((path, s));
+            }
+        }
+    }
+    None
+}
+
+/// Points the workspace member that `cargo new` added for `staging` to `target`.
+fn rename_workspace_member(manifest : &Path, staging : &Path, target : &Path)
+                           -> Result<(), Error> {
+    let path = manifest.to_string_lossy();
+    let s = match try!(read_file(&path)) {
+        Some(s) => s,
+        None => return Ok(()),
+    };
+    let mut doc = try!(merge::parse(&s).map_err(|e| Error::Usage(format!("{}: {}", path, e))));
+    let from = staging.file_name().unwrap_or_default().to_string_lossy();
+    let to = target.file_name().unwrap_or_default().to_string_lossy();
+    if merge::rename_member(&mut doc, &from, &to) {
+        try!(write_file(&path, doc.to_string().as_bytes()));
+    }
+    Ok(())
+}
+
+/// Sets up an existing crate for the board and reports what changed.
+fn init(args : &Args) -> Result<(), Error> {
+    if let Some(ref path) = args.arg_path {
+        try!(std::env::set_current_dir(path).map_err(|ioerr| Error::io(path, ioerr)));
+    }
+    let manifest = try!(manifest());
+    let name = try!(binname(&manifest));
+    // The board of an earlier init is kept unless --board asks for another
+    let config = try!(config::Config::from_manifest(&manifest).map_err(Error::Manifest));
+    let (board, template, required) = try!(project_choices(&args, &config));
+
+    let files = project_files(&template, &required, board);
+    let existed = files.iter().map(|f| Path::new(f).exists()).collect::<Vec<_>>();
+    let kept = try!(set_up_project(&args, Path::new(""), &name, board, &template, &required,
+                                   true));
+    if args.flag_dry_run {
+        println!("Dry run, nothing was written.");
+        return Ok(());
+    }
 
-        write_abi(&args);
-        write_main(&args);
-        write_cargo_helper(&args);
+    println!("Set up {} for the {}:", name, board.description);
+    for (file, existed) in files.iter().zip(existed) {
+        let change = if kept.contains(file) {
+            "kept"
+        } else if existed {
+            "updated"
+        } else {
+            "created"
+        };
+        println!("  {:8} {}", change, file);
+    }
+    Ok(())
+}
+
+/// Builds the project and returns its settings and the path of the ELF file.
+fn build_project(args : &Args) -> Result<(Settings, String), Error> {
+    let manifest = try!(manifest());
+    let binname = try!(binname(&manifest));
+    let config = try!(config::Config::from_manifest(&manifest).map_err(Error::Manifest));
+    let settings = try!(settings(&args, &config));
+    // Checked before the build, which takes a while
+    if args.cmd_run && !settings.boot {
+        return Err(Error::Usage("cargo teensy run starts the firmware to show its output, \
+                                 drop --no-reboot or no-reboot in Cargo.toml".into()));
+    }
+    let wanted = try!(wanted_target(&args));
+    if args.flag_dry_run {
+        println!("Would run: {:?}", build_command(&args, &settings));
+        let elffile = expected_elf(&args, &settings, &binname);
+        return Ok((settings, elffile));
+    }
+    let built = try!(build(&args, &settings));
+    match artifacts::select(&built, wanted, &binname) {
+        Some(artifact) => Ok((settings, artifact.path)),
+        None => {
+            Err(Error::Usage(format!("Cannot tell which executable to use, \
+                                      select one with --bin or --example:\n{}",
+                                     try!(available_targets(&settings)))))
+        }
+    }
+}
+
+/// Builds the project and uploads it.
+fn upload_project(args : &Args) -> Result<(), Error> {
+    let (settings, elffile) = try!(build_project(&args));
+    let hexfile = try!(make_hex(&args, &elffile, EXCLUDED_SECTIONS));
+    if args.flag_dry_run {
+        return upload(&args, &settings, &hexfile);
+    }
+
+    println!("UPLOAD to {} (waiting for reset)", settings.board.description);
+    try!(upload(&args, &settings, &hexfile));
+
+    println!("Upload successful");
+
+    if args.flag_size {
+        let usage = try!(memory_usage(&elffile));
+        println!("{}", size::report(&usage, settings.board));
+    }
+    Ok(())
+}
+
+fn run(args : &Args) -> Result<(), Error> {
+    if args.flag_dry_run && (args.cmd_size || args.cmd_doctor || args.cmd_monitor) {
+        return Err(Error::Usage("--dry-run is for new, init, build, upload and run".into()));
+    }
+    if args.cmd_upload {
+        try!(upload_project(&args));
+    } else if args.cmd_build {
+        let (_, elffile) = try!(build_project(&args));
+        let hexfile = try!(make_hex(&args, &elffile, EXCLUDED_SECTIONS));
+        let (elffile, hexfile) = match args.flag_out {
+            Some(ref dir) => {
+                let copies = try!(copy_artifacts(&args, dir, &[&elffile, &hexfile]));
+                (copies[0].clone(), copies[1].clone())
+            }
+            None => (elffile, hexfile),
+        };
+        println!("ELF: {}", elffile);
+        println!("HEX: {}", hexfile);
+    } else if args.cmd_size {
+        let (settings, elffile) = try!(build_project(&args));
+        let usage = try!(memory_usage(&elffile));
+        println!("{}", size::report(&usage, settings.board));
+        try!(size::check_budget(&usage, settings.board,
+                                settings.flash_budget, settings.ram_budget)
+             .map_err(Error::Budget));
+    } else if args.cmd_new {
+        try!(new(&args));
+    } else if args.cmd_init {
+        try!(init(&args));
+    } else if args.cmd_doctor {
+        try!(doctor(&args));
+    } else if args.cmd_monitor {
+        try!(monitor(&args, Duration::from_secs(0), || Ok(())));
+    } else if args.cmd_run {
+        try!(monitor(&args, Duration::from_secs(RUN_GRACE_SECS), || upload_project(&args)));
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Args = Docopt::new(USAGE)
+                            .and_then(|d| { d.decode() })
+                            .unwrap_or_else(|e| e.exit());
+    if args.flag_version {
+        println!("cargo-teensy {}", env!("CARGO_PKG_VERSION"));
+        return;
+    }
 
-        let mut manifest = manifest().unwrap();
-        update_manifest(&mut manifest);
-        
+    if let Err(e) = run(&args) {
+        let mut stderr = io::stderr();
+        let _ = writeln!(stderr, "Error: {}", e);
+        let _ = writeln!(stderr, "{}", e.fix());
+        process::exit(e.exit_code());
     }
 }
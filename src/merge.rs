@@ -0,0 +1,216 @@
+//! Adds settings to TOML files like Cargo.toml without touching what is
+//! already there. Comments, formatting and the order of keys are kept.
+
+use std::path::Path;
+
+use toml_edit::{DocumentMut, Item, Table, TableLike, Value};
+
+/// A key that has a different value than the one to be added. The existing
+/// value is kept.
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    /// The dotted path of the key, like `dependencies.zinc`.
+    pub key: String,
+    pub existing: String,
+    pub wanted: String,
+}
+
+/// Arrays that choose rather than collect: elements missing there are a
+/// conflict instead of being added, like the default features of a crate.
+const EXCLUSIVE_ARRAYS: &'static [&'static str] = &["features.default"];
+
+pub fn parse(text: &str) -> Result<DocumentMut, String> {
+    text.parse::<DocumentMut>().map_err(|e| format!("{}", e))
+}
+
+/// The value without the whitespace and comments around it.
+fn bare(value: &Value) -> String {
+    let mut value = value.clone();
+    value.decor_mut().clear();
+    value.to_string()
+}
+
+fn show(item: &Item) -> String {
+    match *item {
+        Item::Value(ref value) => bare(value),
+        Item::Table(_) | Item::ArrayOfTables(_) => "a table".into(),
+        Item::None => "nothing".into(),
+    }
+}
+
+/// Moves the tables of a newly inserted `item` behind all existing tables,
+/// in the order they appear in `item`.
+fn place_at_end(item: &mut Item, next_position: &mut usize) {
+    if let Item::Table(ref mut table) = *item {
+        table.set_position(*next_position);
+        *next_position += 1;
+        for (_, child) in table.iter_mut() {
+            place_at_end(child, next_position);
+        }
+    }
+}
+
+fn highest_position(item: &Item) -> usize {
+    match *item {
+        Item::Table(ref table) => {
+            table.iter().map(|(_, child)| highest_position(child))
+                .fold(table.position().unwrap_or(0), ::std::cmp::max)
+        }
+        _ => 0,
+    }
+}
+
+/// Adds the elements of `addition` that `existing` lacks.
+fn merge_arrays(existing: &mut Value, addition: &Value) -> bool {
+    match (existing, addition) {
+        (&mut Value::Array(ref mut existing), &Value::Array(ref addition)) => {
+            for element in addition.iter() {
+                if !existing.iter().any(|e| bare(e) == bare(element)) {
+                    existing.push_formatted(element.clone().decorated(" ", ""));
+                }
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+fn merge_table(table: &mut dyn TableLike, addition: &dyn TableLike, path: &str,
+               next_position: &mut usize, conflicts: &mut Vec<Conflict>) {
+    for (key, added) in addition.iter() {
+        let key_path = if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", path, key)
+        };
+        match table.get_mut(key) {
+            None => {
+                let mut item = added.clone();
+                place_at_end(&mut item, next_position);
+                table.insert(key, item);
+            }
+            Some(existing) => {
+                if let (true, Some(added)) = (existing.is_table_like(), added.as_table_like()) {
+                    let len = existing.as_table_like().map_or(0, |t| t.len());
+                    merge_table(existing.as_table_like_mut().unwrap(), added, &key_path,
+                                next_position, conflicts);
+                    // Keys added to `a = { ... }` need their spaces like the others
+                    if let Some(inline) = existing.as_inline_table_mut() {
+                        if inline.len() != len {
+                            inline.fmt();
+                        }
+                    }
+                    continue;
+                }
+                if let (Some(existing), Some(added)) = (existing.as_value_mut(),
+                                                        added.as_value()) {
+                    let exclusive = EXCLUSIVE_ARRAYS.contains(&&key_path[..]);
+                    if bare(existing) == bare(added) ||
+                       !exclusive && merge_arrays(existing, added) {
+                        continue;
+                    }
+                }
+                conflicts.push(Conflict {
+                    key: key_path,
+                    existing: show(existing),
+                    wanted: show(added),
+                });
+            }
+        }
+    }
+}
+
+/// Adds everything from `addition` that `doc` lacks, recursing into tables
+/// and adding missing array elements, except to `EXCLUSIVE_ARRAYS`. Returns
+/// the keys with other values, which are left alone.
+pub fn merge(doc: &mut DocumentMut, addition: &DocumentMut) -> Vec<Conflict> {
+    let mut next_position = highest_position(doc.as_item()) + 1;
+    let mut conflicts = Vec::new();
+    merge_table(doc.as_table_mut(), addition.as_table(), "", &mut next_position, &mut conflicts);
+    conflicts
+}
+
+/// Sets the value at the dotted `path`, creating missing tables. Nothing
+/// happens if a part of the path is there but not a table.
+pub fn set(doc: &mut DocumentMut, path: &[&str], value: Value) {
+    let (key, tables) = match path.split_last() {
+        Some(split) => split,
+        None => return,
+    };
+    let mut table: &mut dyn TableLike = doc.as_table_mut();
+    for (i, name) in tables.iter().enumerate() {
+        if table.get(name).is_none() {
+            let mut child = Table::new();
+            // Only the innermost table gets a [header]
+            if i + 1 < tables.len() {
+                child.set_implicit(true);
+            } else {
+                child.decor_mut().set_prefix("\n");
+            }
+            table.insert(name, Item::Table(child));
+        }
+        table = match table.get_mut(name).and_then(|item| item.as_table_like_mut()) {
+            Some(child) => child,
+            None => return,
+        };
+    }
+    table.insert(key, Item::Value(value));
+}
+
+/// Renames the member directory `from` to `to` in `workspace.members`,
+/// keeping the path in front of it. Returns whether a member was renamed.
+pub fn rename_member(doc: &mut DocumentMut, from: &str, to: &str) -> bool {
+    let members = match doc.get_mut("workspace").and_then(|w| w.get_mut("members"))
+                           .and_then(|m| m.as_array_mut()) {
+        Some(members) => members,
+        None => return false,
+    };
+    let mut renamed = false;
+    for member in members.iter_mut() {
+        let path = match member.as_str() {
+            Some(path) if Path::new(path).file_name().map_or(false, |n| n == from) => {
+                Path::new(path).with_file_name(to).to_string_lossy().into_owned()
+            }
+            _ => continue,
+        };
+        let decor = member.decor().clone();
+        *member = Value::from(path);
+        *member.decor_mut() = decor;
+        renamed = true;
+    }
+    renamed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_adds_missing_array_elements() {
+        let mut doc = parse("[dependencies]\nzinc = { features = [\"a\"] }\n").unwrap();
+        let addition = parse("[dependencies]\nzinc = { features = [\"b\"] }\n").unwrap();
+        assert!(merge(&mut doc, &addition).is_empty());
+        assert_eq!(doc.to_string(), "[dependencies]\nzinc = { features = [\"a\", \"b\"] }\n");
+    }
+
+    #[test]
+    fn merge_reports_other_default_features() {
+        let text = "[features]\ndefault = [\"std\"]\n";
+        let mut doc = parse(text).unwrap();
+        let addition = parse("[features]\ndefault = [\"mcu_k20\"]\n").unwrap();
+        let conflicts = merge(&mut doc, &addition);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].key, "features.default");
+        assert_eq!(conflicts[0].existing, "[\"std\"]");
+        assert_eq!(conflicts[0].wanted, "[\"mcu_k20\"]");
+        assert_eq!(doc.to_string(), text);
+    }
+
+    #[test]
+    fn rename_member_keeps_the_directory() {
+        let mut doc = parse("[workspace]\nmembers = [\"a\", \"boards/.blink.cargo-teensy-7\"]\n")
+            .unwrap();
+        assert!(rename_member(&mut doc, ".blink.cargo-teensy-7", "blink"));
+        assert_eq!(doc.to_string(), "[workspace]\nmembers = [\"a\", \"boards/blink\"]\n");
+    }
+}
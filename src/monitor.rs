@@ -0,0 +1,381 @@
+//! `cargo teensy monitor`: shows what the Teensy prints on its USB serial
+//! port and sends what is typed. When the board resets and its port
+//! disappears, the monitor waits for it to come back.
+
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libc;
+
+use error::Error;
+use serial::{self, Port};
+
+/// How long to wait for data before looking at the input again.
+const POLL_MS: i32 = 50;
+/// How often to look for the port while it is gone.
+const RECONNECT_MS: u64 = 200;
+
+pub struct Options {
+    /// The port to open, or `None` for the first Teensy found.
+    pub port: Option<String>,
+    pub baud: u32,
+    /// Sent after each line of input.
+    pub eol: &'static str,
+    /// Show the lines sent to the board.
+    pub echo: bool,
+    /// Prefix received lines with the seconds since the monitor started.
+    pub timestamps: bool,
+    /// How long a port that is there but cannot be opened yet is retried
+    /// before the first connection, like right after an upload.
+    pub grace: Duration,
+}
+
+/// The line ending for `--eol`.
+pub fn eol(name: &str) -> Option<&'static str> {
+    match name {
+        "lf" => Some("\n"),
+        "cr" => Some("\r"),
+        "crlf" => Some("\r\n"),
+        _ => None,
+    }
+}
+
+/// Reads stdin line by line, so that the port can be read meanwhile.
+fn spawn_input() -> Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            match line {
+                Ok(line) => if tx.send(line).is_err() { break },
+                Err(_) => break,
+            }
+        }
+    });
+    rx
+}
+
+/// The received data, on stdout, and notes about the connection, on stderr.
+struct Output {
+    out: Box<dyn Write>,
+    notes: Box<dyn Write>,
+    start: Instant,
+    timestamps: bool,
+    at_line_start: bool,
+}
+
+impl Output {
+    fn new(options: &Options, out: Box<dyn Write>, notes: Box<dyn Write>) -> Output {
+        Output { out: out, notes: notes, start: Instant::now(), timestamps: options.timestamps,
+                 at_line_start: true }
+    }
+
+    fn prefix(&mut self) {
+        if self.timestamps {
+            let elapsed = self.start.elapsed();
+            let _ = write!(self.out, "[{:>4}.{:03}] ", elapsed.as_secs(), elapsed.subsec_millis());
+        }
+    }
+
+    fn received(&mut self, data: &[u8]) {
+        let mut rest = data;
+        while !rest.is_empty() {
+            if self.at_line_start {
+                self.prefix();
+            }
+            let end = rest.iter().position(|&b| b == b'\n').map_or(rest.len(), |i| i + 1);
+            let _ = self.out.write_all(&rest[..end]);
+            self.at_line_start = rest[end - 1] == b'\n';
+            rest = &rest[end..];
+        }
+        let _ = self.out.flush();
+    }
+
+    fn sent(&mut self, line: &str) {
+        self.end_line();
+        self.prefix();
+        let _ = writeln!(self.out, "> {}", line);
+        let _ = self.out.flush();
+    }
+
+    fn note(&mut self, msg: &str) {
+        self.end_line();
+        let _ = writeln!(self.notes, "[{}]", msg);
+    }
+
+    /// Finishes a line the board left open, so that the next starts at the margin.
+    fn end_line(&mut self) {
+        if !self.at_line_start {
+            let _ = writeln!(self.out, "");
+            let _ = self.out.flush();
+            self.at_line_start = true;
+        }
+    }
+}
+
+/// Opens the port, waiting for it to appear. Before the first connection,
+/// errors other than a missing port are given up on, except for a busy or
+/// inaccessible port during the grace period. Later they are retried, as the
+/// permissions of a new device node may not be set up yet. `None` once
+/// `running` is cleared.
+fn connect(options: &Options, output: &mut Output, input: &Receiver<String>, first: bool,
+           running: &AtomicBool) -> Result<Option<Port>, Error> {
+    let mut waiting = false;
+    let start = Instant::now();
+    while running.load(Ordering::SeqCst) {
+        if let Some(path) = options.port.clone().or_else(serial::find) {
+            match Port::open(&path, options.baud) {
+                Ok(port) => return Ok(Some(port)),
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+                // udev may still be setting up the device node of a fresh upload
+                Err(ref e) if first && start.elapsed() < options.grace &&
+                              (e.raw_os_error() == Some(libc::EACCES) ||
+                               e.raw_os_error() == Some(libc::EBUSY)) => {}
+                Err(e) => if first { return Err(Error::io(&path, e)) },
+            }
+        }
+        if !waiting {
+            output.note(&match options.port {
+                Some(ref path) => format!("Waiting for {}", path),
+                None => "Waiting for a Teensy in USB serial mode".into(),
+            });
+            waiting = true;
+        }
+        if input.try_iter().count() > 0 {
+            output.note("Not connected, input dropped");
+        }
+        thread::sleep(Duration::from_millis(RECONNECT_MS));
+    }
+    Ok(None)
+}
+
+/// Passes data both ways until the port is gone or `running` is cleared.
+fn session(port: &mut Port, options: &Options, output: &mut Output, input: &Receiver<String>,
+           running: &AtomicBool) -> io::Result<()> {
+    let mut buf = [0; 4096];
+    while running.load(Ordering::SeqCst) {
+        let n = try!(port.read_timeout(&mut buf, POLL_MS));
+        output.received(&buf[..n]);
+        for line in input.try_iter() {
+            if options.echo {
+                output.sent(&line);
+            }
+            try!(port.write_all(format!("{}{}", line, options.eol).as_bytes()));
+        }
+    }
+    Ok(())
+}
+
+/// Connects again and again until `running` is cleared.
+fn serve(options: &Options, output: &mut Output, input: &Receiver<String>, running: &AtomicBool)
+         -> Result<(), Error> {
+    let mut first = true;
+    while let Some(mut port) = try!(connect(options, output, input, first, running)) {
+        first = false;
+        output.note(&format!("Connected to {} (Ctrl-C to quit)", port.path));
+        if let Err(e) = session(&mut port, options, output, input, running) {
+            output.note(&format!("{}: {}", port.path, e));
+        }
+    }
+    Ok(())
+}
+
+/// Runs until it is interrupted with Ctrl-C.
+pub fn run(options: &Options) -> Result<(), Error> {
+    let input = spawn_input();
+    let mut output = Output::new(options, Box::new(io::stdout()), Box::new(io::stderr()));
+    serve(options, &mut output, &input, &AtomicBool::new(true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::os::unix::fs::symlink;
+    use std::os::unix::io::RawFd;
+    use std::ptr;
+    use std::sync::{Arc, Mutex};
+    use std::sync::mpsc::Sender;
+
+    /// A `Write` the test can look into while the monitor writes to it.
+    #[derive(Clone)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Shared {
+        fn text(&self) -> String {
+            String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+        }
+    }
+
+    impl Write for Shared {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(data);
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A pseudo-terminal standing in for the board: the monitor opens the
+    /// slave by its path, the test talks through the master.
+    struct Pty {
+        master: RawFd,
+        path: String,
+    }
+
+    impl Pty {
+        fn open() -> Pty {
+            let (mut master, mut slave) = (0, 0);
+            let mut name = [0 as libc::c_char; 64];
+            assert_eq!(unsafe {
+                libc::openpty(&mut master, &mut slave, name.as_mut_ptr(), ptr::null(),
+                              ptr::null())
+            }, 0);
+            // The monitor opens its own descriptor
+            unsafe { libc::close(slave) };
+            let path = unsafe { ::std::ffi::CStr::from_ptr(name.as_ptr()) };
+            Pty { master: master, path: path.to_string_lossy().into_owned() }
+        }
+
+        fn write(&self, data: &str) {
+            let n = unsafe { libc::write(self.master, data.as_ptr() as *const _, data.len()) };
+            assert_eq!(n, data.len() as isize);
+        }
+
+        /// Reads until `expected` bytes arrived or two seconds passed.
+        fn read(&self, expected: usize) -> String {
+            let mut data = Vec::new();
+            let mut buf = [0u8; 256];
+            let deadline = Instant::now() + Duration::from_secs(2);
+            while data.len() < expected && Instant::now() < deadline {
+                let mut fds = libc::pollfd { fd: self.master, events: libc::POLLIN, revents: 0 };
+                if unsafe { libc::poll(&mut fds, 1, 50) } > 0 {
+                    let n = unsafe {
+                        libc::read(self.master, buf.as_mut_ptr() as *mut _, buf.len())
+                    };
+                    if n <= 0 {
+                        break;
+                    }
+                    data.extend_from_slice(&buf[..n as usize]);
+                }
+            }
+            String::from_utf8_lossy(&data).into_owned()
+        }
+
+        /// Hangs up, like a board that resets.
+        fn close(self) {
+            unsafe { libc::close(self.master) };
+        }
+    }
+
+    /// Waits up to two seconds for `shared` to contain `text` `times` times.
+    fn wait_for(shared: &Shared, text: &str, times: usize) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while Instant::now() < deadline {
+            if shared.text().matches(text).count() >= times {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    struct Monitor {
+        out: Shared,
+        notes: Shared,
+        input: Sender<String>,
+        running: Arc<AtomicBool>,
+        thread: thread::JoinHandle<Result<(), Error>>,
+    }
+
+    fn start(port: &str, echo: bool, timestamps: bool) -> Monitor {
+        let out = Shared(Arc::new(Mutex::new(Vec::new())));
+        let notes = Shared(Arc::new(Mutex::new(Vec::new())));
+        let (input, rx) = mpsc::channel();
+        let running = Arc::new(AtomicBool::new(true));
+        let options = Options { port: Some(port.into()), baud: 115200, eol: "\r\n", echo: echo,
+                                timestamps: timestamps, grace: Duration::from_secs(0) };
+        let (o, n, r) = (out.clone(), notes.clone(), running.clone());
+        let thread = thread::spawn(move || {
+            let mut output = Output::new(&options, Box::new(o), Box::new(n));
+            serve(&options, &mut output, &rx, &r)
+        });
+        Monitor { out: out, notes: notes, input: input, running: running, thread: thread }
+    }
+
+    impl Monitor {
+        fn stop(self) -> Result<(), Error> {
+            self.running.store(false, Ordering::SeqCst);
+            self.thread.join().unwrap()
+        }
+    }
+
+    #[test]
+    fn monitor_sends_lines_with_the_eol_and_echoes_them() {
+        let pty = Pty::open();
+        let monitor = start(&pty.path, true, false);
+        assert!(wait_for(&monitor.notes, "Connected to", 1));
+
+        monitor.input.send("ping".into()).unwrap();
+        assert_eq!(pty.read(6), "ping\r\n");
+        assert!(wait_for(&monitor.out, "> ping\n", 1));
+        pty.write("pong\n");
+        assert!(wait_for(&monitor.out, "pong\n", 1));
+
+        assert!(monitor.stop().is_ok());
+        pty.close();
+    }
+
+    #[test]
+    fn monitor_prefixes_received_lines_with_timestamps() {
+        let pty = Pty::open();
+        let monitor = start(&pty.path, false, true);
+        assert!(wait_for(&monitor.notes, "Connected to", 1));
+
+        pty.write("one\ntw");
+        assert!(wait_for(&monitor.out, "tw", 1));
+        pty.write("o\n");
+        assert!(wait_for(&monitor.out, "two\n", 1));
+
+        let out = monitor.out.text();
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2, "{:?}", out);
+        assert!(lines[0].starts_with("[   0.") && lines[0].ends_with("] one"), "{:?}", out);
+        assert!(lines[1].starts_with("[   0.") && lines[1].ends_with("] two"), "{:?}", out);
+
+        assert!(monitor.stop().is_ok());
+        pty.close();
+    }
+
+    #[test]
+    fn monitor_reconnects_after_a_hang_up() {
+        let link = env::temp_dir().join(format!("cargo-teensy-monitor-{}", ::std::process::id()));
+        let link_name = link.to_string_lossy().into_owned();
+        let _ = fs::remove_file(&link);
+        let first = Pty::open();
+        symlink(&first.path, &link).unwrap();
+        let monitor = start(&link_name, false, false);
+        assert!(wait_for(&monitor.notes, "Connected to", 1));
+        first.write("before\n");
+        assert!(wait_for(&monitor.out, "before\n", 1));
+
+        // The board comes back as another device behind the same name
+        let second = Pty::open();
+        fs::remove_file(&link).unwrap();
+        symlink(&second.path, &link).unwrap();
+        first.close();
+        assert!(wait_for(&monitor.notes, "Connected to", 2), "{:?}", monitor.notes.text());
+        second.write("after\n");
+        assert!(wait_for(&monitor.out, "after\n", 1));
+
+        assert!(monitor.stop().is_ok());
+        second.close();
+        let _ = fs::remove_file(&link);
+    }
+}
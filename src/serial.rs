@@ -0,0 +1,189 @@
+//! The USB serial port of a Teensy, opened raw with termios. Any other
+//! terminal device, like a pseudo-terminal, works as well.
+
+use std::ffi::CString;
+use std::fs;
+use std::io::{self, Write};
+use std::mem;
+use std::os::unix::io::RawFd;
+
+use libc;
+
+#[cfg(target_os = "linux")]
+use halfkay::{VENDOR_ID, SERIAL_PRODUCT_ID};
+
+/// The baud rates that can be set. USB serial ignores them, but real UARTs
+/// and USB serial adapters do not.
+pub const BAUD_RATES: &'static [u32] = &[1200, 2400, 4800, 9600, 19200, 38400, 57600, 115200,
+                                         230400];
+
+pub struct Port {
+    fd: RawFd,
+    pub path: String,
+}
+
+fn speed(baud: u32) -> libc::speed_t {
+    match baud {
+        134 => libc::B134,
+        1200 => libc::B1200,
+        2400 => libc::B2400,
+        4800 => libc::B4800,
+        9600 => libc::B9600,
+        19200 => libc::B19200,
+        38400 => libc::B38400,
+        57600 => libc::B57600,
+        230400 => libc::B230400,
+        _ => libc::B115200,
+    }
+}
+
+fn disconnected() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "device disconnected")
+}
+
+impl Port {
+    /// Opens the terminal device at `path` without line editing or echo.
+    pub fn open(path: &str, baud: u32) -> io::Result<Port> {
+        let cpath = try!(CString::new(path)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "NUL in path")));
+        let fd = unsafe {
+            libc::open(cpath.as_ptr(), libc::O_RDWR | libc::O_NOCTTY | libc::O_NONBLOCK)
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Closed by drop if the settings fail
+        let port = Port { fd: fd, path: path.into() };
+
+        let mut tio: libc::termios = unsafe { mem::zeroed() };
+        if unsafe { libc::tcgetattr(fd, &mut tio) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        unsafe {
+            libc::cfmakeraw(&mut tio);
+            libc::cfsetispeed(&mut tio, speed(baud));
+            libc::cfsetospeed(&mut tio, speed(baud));
+        }
+        tio.c_cflag |= libc::CLOCAL | libc::CREAD;
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &tio) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(port)
+    }
+
+    /// Waits up to `timeout_ms` for `events`. Returns false on timeout.
+    fn wait(&self, events: libc::c_short, timeout_ms: i32) -> io::Result<bool> {
+        let mut pfd = libc::pollfd { fd: self.fd, events: events, revents: 0 };
+        match unsafe { libc::poll(&mut pfd, 1, timeout_ms) } {
+            0 => Ok(false),
+            n if n < 0 => {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted { Ok(false) } else { Err(err) }
+            }
+            _ if pfd.revents & events == 0 => Err(disconnected()),
+            _ => Ok(true),
+        }
+    }
+
+    /// Reads what arrives within `timeout_ms`. `Ok(0)` means nothing did, an
+    /// error that the device is gone.
+    pub fn read_timeout(&mut self, buf: &mut [u8], timeout_ms: i32) -> io::Result<usize> {
+        if !try!(self.wait(libc::POLLIN, timeout_ms)) {
+            return Ok(0);
+        }
+        let n = unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if n < 0 {
+            let err = io::Error::last_os_error();
+            return if err.kind() == io::ErrorKind::WouldBlock { Ok(0) } else { Err(err) };
+        }
+        // A terminal reads end of file when it was hung up
+        if n == 0 {
+            return Err(disconnected());
+        }
+        Ok(n as usize)
+    }
+}
+
+impl Write for Port {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        loop {
+            let n = unsafe {
+                libc::write(self.fd, data.as_ptr() as *const libc::c_void, data.len())
+            };
+            if n >= 0 {
+                return Ok(n as usize);
+            }
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::WouldBlock {
+                return Err(err);
+            }
+            try!(self.wait(libc::POLLOUT, 1000));
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Port {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// Whether the tty at `/sys/class/tty/<name>` belongs to a Teensy. Its
+/// `device` is the USB interface, whose parent holds the ids.
+#[cfg(target_os = "linux")]
+fn is_teensy(name: &str) -> bool {
+    let interface = match fs::canonicalize(format!("/sys/class/tty/{}/device", name)) {
+        Ok(interface) => interface,
+        Err(_) => return false,
+    };
+    let id = |file: &str| {
+        interface.parent()
+            .and_then(|usb| fs::read_to_string(usb.join(file)).ok())
+            .and_then(|id| u16::from_str_radix(id.trim(), 16).ok())
+    };
+    id("idVendor") == Some(VENDOR_ID) && id("idProduct") == Some(SERIAL_PRODUCT_ID)
+}
+
+/// The serial port of the first Teensy found, like `/dev/ttyACM0`.
+#[cfg(target_os = "linux")]
+pub fn find() -> Option<String> {
+    let mut names = match fs::read_dir("/sys/class/tty") {
+        Ok(entries) => {
+            entries.filter_map(|e| e.ok())
+                .map(|e| e.file_name().to_string_lossy().into_owned())
+                .filter(|name| is_teensy(name))
+                .collect::<Vec<_>>()
+        }
+        Err(_) => return None,
+    };
+    names.sort();
+    names.into_iter().next().map(|name| format!("/dev/{}", name))
+}
+
+/// The first USB modem port, like `/dev/cu.usbmodem12341`. macOS does not
+/// tell the vendor in the name, so this may be another board.
+#[cfg(target_os = "macos")]
+pub fn find() -> Option<String> {
+    let mut names = match fs::read_dir("/dev") {
+        Ok(entries) => {
+            entries.filter_map(|e| e.ok())
+                .map(|e| e.file_name().to_string_lossy().into_owned())
+                .filter(|name| name.starts_with("cu.usbmodem"))
+                .collect::<Vec<_>>()
+        }
+        Err(_) => return None,
+    };
+    names.sort();
+    names.into_iter().next().map(|name| format!("/dev/{}", name))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn find() -> Option<String> {
+    None
+}
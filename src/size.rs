@@ -0,0 +1,83 @@
+//! Flash and RAM usage of a built firmware.
+
+use boards::Board;
+use elf::{Elf, SHT_NOBITS, SHF_ALLOC, SHF_WRITE, SHF_EXECINSTR};
+
+#[derive(Debug, Default)]
+pub struct Usage {
+    pub text: u32,
+    pub rodata: u32,
+    pub data: u32,
+    pub bss: u32,
+}
+
+impl Usage {
+    /// Sums up the allocated sections of `elf`, leaving out the `exclude`d ones.
+    pub fn from_elf(elf: &Elf, exclude: &[&str]) -> Usage {
+        let mut usage = Usage::default();
+        for section in &elf.sections {
+            if section.flags & SHF_ALLOC == 0 || exclude.iter().any(|e| *e == section.name) {
+                continue;
+            }
+            if section.kind == SHT_NOBITS {
+                usage.bss += section.size;
+            } else if section.flags & SHF_EXECINSTR != 0 {
+                usage.text += section.size;
+            } else if section.flags & SHF_WRITE != 0 {
+                usage.data += section.size;
+            } else {
+                usage.rodata += section.size;
+            }
+        }
+        usage
+    }
+
+    /// Code, constants and the initial values of `.data` all live in flash.
+    pub fn flash(&self) -> u32 {
+        self.text + self.rodata + self.data
+    }
+
+    pub fn ram(&self) -> u32 {
+        self.data + self.bss
+    }
+}
+
+fn percent(used: u32, size: u32) -> f64 {
+    used as f64 * 100.0 / size as f64
+}
+
+fn region_line(name: &str, used: u32, size: u32) -> String {
+    let free = if used > size { 0 } else { size - used };
+    format!("{:<8}{:>10}{:>10}{:>10}{:>8.1}%", name, used, size, free, percent(used, size))
+}
+
+pub fn report(usage: &Usage, board: &Board) -> String {
+    let lines = vec![
+        format!("Memory usage on {} ({}):", board.description, board.mcu),
+        format!("  text {}  rodata {}  data {}  bss {}",
+                usage.text, usage.rodata, usage.data, usage.bss),
+        format!("{:<8}{:>10}{:>10}{:>10}{:>9}", "Region", "Used", "Size", "Free", "Use"),
+        region_line("FLASH", usage.flash(), board.flash_size),
+        region_line("RAM", usage.ram(), board.ram_size),
+        "(RAM does not include the stack and the heap)".into(),
+    ];
+    lines.join("\n")
+}
+
+/// Checks the usage against budgets given in percent of the board's capacity.
+pub fn check_budget(usage: &Usage, board: &Board, flash: u32, ram: u32) -> Result<(), String> {
+    let mut exceeded = Vec::new();
+    if percent(usage.flash(), board.flash_size) > flash as f64 {
+        exceeded.push(format!("FLASH usage of {:.1}% exceeds the budget of {}%",
+                              percent(usage.flash(), board.flash_size), flash));
+    }
+    if percent(usage.ram(), board.ram_size) > ram as f64 {
+        exceeded.push(format!("RAM usage of {:.1}% exceeds the budget of {}%",
+                              percent(usage.ram(), board.ram_size), ram));
+    }
+    if exceeded.is_empty() {
+        Ok(())
+    } else {
+        Err(exceeded.join("\n"))
+    }
+}
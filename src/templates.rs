@@ -0,0 +1,900 @@
+//! The project templates of `cargo teensy new`.
+//!
+//! Besides the built-in templates, a template can be a directory, either
+//! given by path or found by name in the user template directory
+//! (`~/.config/cargo-teensy/templates/<name>`), or a git repository with the
+//! same layout:
+//!
+//! ```text
+//! template.toml   description = "...", kind = "bin" or "lib",
+//!                 target-spec = false, toolchain = "stable",
+//!                 boards = ["teensy31", ...] (all optional)
+//! manifest.toml   added to Cargo.toml (optional)
+//! files/          copied into the project, e.g. files/src/main.rs
+//! ```
+//!
+//! Without `target-spec = false` the project gets the generated target
+//! specification, and without `toolchain` the toolchain zinc requires. A
+//! template with its own `.cargo/config` or `.cargo/config.toml` gets it
+//! merged into the project's cargo configuration instead of the generated
+//! settings.
+//!
+//! All files may contain the placeholders `{name}`, `{board}`,
+//! `{description}`, `{target}`, `{cpu}`, `{feature}`, `{led_pin}`,
+//! `{led_port}`, `{led_bit}`, `{flash_size}`, `{ram_start}` and `{ram_size}`.
+//! Doubled braces escape a placeholder: `{{name}}` becomes `{name}`. Other
+//! braces are left alone.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use toml;
+
+use error::Error;
+
+pub const DEFAULT_TEMPLATE: &'static str = "blink";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Bin,
+    Lib,
+}
+
+#[derive(Debug, Clone)]
+pub struct Template {
+    pub name: String,
+    pub description: String,
+    pub kind: Kind,
+    /// Paths relative to the project and their contents.
+    pub files: Vec<(String, String)>,
+    /// TOML added to Cargo.toml.
+    pub manifest: String,
+    /// Whether the project builds with the generated target specification.
+    pub target_spec: bool,
+    /// The toolchain the project uses. `None` for the one zinc requires.
+    pub toolchain: Option<String>,
+    /// The `--board` names the template works for, empty for all.
+    pub boards: Vec<String>,
+}
+
+/// The boards zinc has a HAL for. Its blink example also uses the
+/// Cortex-M4 SysTick, which the Teensy LC lacks.
+const ZINC_BOARDS: &'static [&'static str] = &["teensy30", "teensy31", "teensy32"];
+
+/// The boards the teensy3 crate is written for, both with the MK20DX256.
+const TEENSY3_BOARDS: &'static [&'static str] = &["teensy31", "teensy32"];
+
+/// A nightly that teensy3 0.1 builds with and that rustup has the
+/// `thumbv7em-none-eabi` standard library for.
+const TEENSY3_TOOLCHAIN: &'static str = "nightly-2018-05-15";
+
+const BLINK_MAIN: &'static str = r#"
+#![feature(plugin, start)]
+#![no_std]
+#![plugin(macro_zinc)]
+
+extern crate zinc;
+
+use core::option::Option::Some;
+
+use zinc::hal::cortex_m4::systick;
+use zinc::hal::k20::{pin, watchdog};
+use zinc::hal::pin::Gpio;
+
+/// Wait the given number of SysTick ticks
+pub fn wait(ticks: u32) {
+  let mut n = ticks;
+  // Reset the tick flag
+  systick::tick();
+  loop {
+    if systick::tick() {
+      n -= 1;
+      if n == 0 {
+        break;
+      }
+    }
+  }
+}
+
+#[zinc_main]
+pub fn main() {
+  zinc::hal::mem_init::init_stack();
+  zinc::hal::mem_init::init_data();
+  watchdog::init(watchdog::State::Disabled);
+
+  // The LED of the {description} is on pin {led_pin}
+  let led1 = pin::Pin::new(pin::Port::Port{led_port}, {led_bit}, pin::Function::Gpio, Some(zinc::hal::pin::Out));
+
+  systick::setup(systick::ten_ms().unwrap_or(480000));
+  systick::enable();
+  loop {
+    led1.set_high();
+    wait(10);
+    led1.set_low();
+    wait(10);
+  }
+}
+"#;
+
+const ZINC_MANIFEST: &'static str = r#"
+[features]
+default = ["{feature}"]
+{feature} = ["zinc/{feature}"] # also enables the {feature} feature in the zinc crate
+
+[dependencies]
+rust-libcore = "*"
+
+[dependencies.zinc]
+git = "https://github.com/hackndev/zinc.git"
+branch = "master"
+
+[dependencies.macro_zinc]
+git = "https://github.com/hackndev/zinc.git"
+branch = "master"
+path = "macro_zinc"
+
+"#;
+
+const USB_SERIAL_MAIN: &'static str = r#"//! {name}: echoes everything received over USB serial and toggles the LED
+//! of the {description} for each byte.
+
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate teensy3;
+
+use teensy3::bindings;
+use teensy3::serial::Serial;
+
+#[no_mangle]
+pub unsafe extern fn main() {
+    let serial = Serial{};
+    let mut led = false;
+    bindings::pinMode({led_pin}, bindings::OUTPUT as u8);
+
+    loop {
+        if let Ok(byte) = serial.try_read_byte() {
+            let _ = serial.write_bytes(&[byte]);
+            led = !led;
+            bindings::digitalWrite({led_pin}, led as u8);
+        }
+    }
+}
+"#;
+
+const TEENSY3_MANIFEST: &'static str = r#"
+[dependencies]
+teensy3 = "0.1"
+
+[profile.dev]
+panic = "abort"
+
+[profile.release]
+panic = "abort"
+"#;
+
+const MINIMAL_MAIN: &'static str = r#"//! {name}: the least a program for the {description} needs. It disables
+//! the watchdog, initializes RAM and turns on the LED on pin {led_pin}.
+
+#![feature(lang_items)]
+#![no_std]
+#![no_main]
+
+use core::ptr;
+
+extern {
+    static _sidata: u32;
+    static mut _sdata: u32;
+    static mut _edata: u32;
+    static mut _sbss: u32;
+    static mut _ebss: u32;
+}
+
+const LED_PORT: u32 = b'{led_port}' as u32 - b'A' as u32;
+const LED_BIT: u32 = {led_bit};
+
+const SIM_SCGC5: *mut u32 = 0x4004_8038 as *mut u32;
+const PORT_PCR: u32 = 0x4004_9000;
+const GPIO: u32 = 0x400F_F000;
+
+#[link_section = ".vector_table.reset"]
+#[no_mangle]
+pub static RESET_VECTOR: unsafe extern fn() -> ! = reset;
+
+/// Leaves the flash unsecured, so that HalfKay can program it again.
+#[link_section = ".flashconfig"]
+#[no_mangle]
+pub static FLASH_CONFIG: [u8; 16] = [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+                                     0xFF, 0xFF, 0xFF, 0xFF, 0xDE, 0xF9, 0xFF, 0xFF];
+
+#[cfg(not(feature = "mcu_kl26"))]
+unsafe fn disable_watchdog() {
+    ptr::write_volatile(0x4005_200E as *mut u16, 0xC520);
+    ptr::write_volatile(0x4005_200E as *mut u16, 0xD928);
+    ptr::write_volatile(0x4005_2000 as *mut u16, 0x01D2);
+}
+
+#[cfg(feature = "mcu_kl26")]
+unsafe fn disable_watchdog() {
+    ptr::write_volatile(0x4004_8100 as *mut u32, 0);
+}
+
+unsafe fn init_ram() {
+    let mut src = &_sidata as *const u32;
+    let mut dst = &mut _sdata as *mut u32;
+    while dst < &mut _edata as *mut u32 {
+        ptr::write_volatile(dst, ptr::read(src));
+        src = src.offset(1);
+        dst = dst.offset(1);
+    }
+    let mut dst = &mut _sbss as *mut u32;
+    while dst < &mut _ebss as *mut u32 {
+        ptr::write_volatile(dst, 0);
+        dst = dst.offset(1);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern fn reset() -> ! {
+    disable_watchdog();
+    init_ram();
+
+    // Clock the port, make the pin a GPIO output and drive it high
+    ptr::write_volatile(SIM_SCGC5, ptr::read_volatile(SIM_SCGC5) | 1 << (9 + LED_PORT));
+    ptr::write_volatile((PORT_PCR + LED_PORT * 0x1000 + LED_BIT * 4) as *mut u32, 1 << 8);
+    ptr::write_volatile((GPIO + LED_PORT * 0x40 + 0x14) as *mut u32, 1 << LED_BIT);
+    ptr::write_volatile((GPIO + LED_PORT * 0x40 + 0x04) as *mut u32, 1 << LED_BIT);
+
+    loop {}
+}
+
+#[lang = "panic_fmt"]
+extern fn panic_fmt() -> ! {
+    loop {}
+}
+
+#[lang = "eh_personality"]
+extern fn eh_personality() {}
+"#;
+
+const MINIMAL_LAYOUT: &'static str = r#"/* Memory layout of the {description} */
+
+MEMORY
+{
+  FLASH (rx) : ORIGIN = 0x00000000, LENGTH = {flash_size}
+  RAM (rwx)  : ORIGIN = {ram_start}, LENGTH = {ram_size}
+}
+
+ENTRY(reset)
+
+SECTIONS
+{
+  .text : {
+    LONG(ORIGIN(RAM) + LENGTH(RAM))
+    KEEP(*(.vector_table.reset))
+    . = 0x400;
+    KEEP(*(.flashconfig))
+    *(.text*)
+    *(.rodata*)
+    . = ALIGN(4);
+  } > FLASH
+
+  .data : {
+    _sdata = .;
+    *(.data*)
+    . = ALIGN(4);
+    _edata = .;
+  } > RAM AT > FLASH
+  _sidata = LOADADDR(.data);
+
+  .bss (NOLOAD) : {
+    _sbss = .;
+    *(.bss*)
+    *(COMMON)
+    . = ALIGN(4);
+    _ebss = .;
+  } > RAM
+
+  /DISCARD/ : {
+    *(.ARM.exidx*)
+  }
+}
+"#;
+
+const MINIMAL_MANIFEST: &'static str = r#"
+[features]
+default = ["{feature}"]
+{feature} = []
+
+[dependencies]
+rust-libcore = "*"
+"#;
+
+const LIBRARY_LIB: &'static str = r#"//! {name}: a library for the {description}.
+
+#![no_std]
+
+/// The Arduino pin number of the LED.
+pub const LED_PIN: u32 = {led_pin};
+"#;
+
+const LIBRARY_MANIFEST: &'static str = r#"
+[dependencies]
+rust-libcore = "*"
+"#;
+
+const CORTEX_M_RT_MAIN: &'static str = r#"//! {name}: blinks the LED on pin {led_pin} of the {description}.
+
+#![no_std]
+#![no_main]
+
+mod hal;
+
+use core::panic::PanicInfo;
+
+use cortex_m_rt::entry;
+use embedded_hal::digital::StatefulOutputPin;
+
+const LED_PORT: u32 = b'{led_port}' as u32 - b'A' as u32;
+const LED_BIT: u32 = {led_bit};
+
+/// Leaves the flash unsecured, so that HalfKay can program it again.
+#[link_section = ".flashconfig"]
+#[used]
+#[no_mangle]
+pub static FLASH_CONFIG: [u8; 16] = [
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xDE, 0xF9, 0xFF, 0xFF,
+];
+
+// The watchdog is already disabled, see hal.rs
+#[entry]
+fn main() -> ! {
+    // This is the only handle to the LED pin
+    let mut led = unsafe { hal::Output::new(LED_PORT, LED_BIT) };
+
+    loop {
+        // Toggle the LED, about twice a second with the reset clock of ~21 MHz
+        led.toggle().unwrap();
+        cortex_m::asm::delay(10_000_000);
+    }
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    loop {
+        cortex_m::asm::nop();
+    }
+}
+"#;
+
+const CORTEX_M_RT_HAL: &'static str = r#"//! Just enough of a HAL for the {description}: the watchdog, and GPIO
+//! output pins that implement the `embedded-hal` traits, so that drivers
+//! written against them work. Replace it with a Kinetis HAL crate once one
+//! covers your board.
+
+use core::convert::Infallible;
+use core::ptr;
+
+use embedded_hal::digital::{ErrorType, OutputPin, StatefulOutputPin};
+
+const SIM_SCGC5: *mut u32 = 0x4004_8038 as *mut u32;
+const PORT_PCR: u32 = 0x4004_9000;
+const GPIO: u32 = 0x400F_F000;
+
+// GPIO register offsets
+const PDOR: u32 = 0x00;
+const PSOR: u32 = 0x04;
+const PCOR: u32 = 0x08;
+const PTOR: u32 = 0x0C;
+const PDDR: u32 = 0x14;
+
+// The watchdog resets the chip unless it is serviced, and would do so while
+// a large .bss is still being zeroed. cortex-m-rt calls `__pre_init` before
+// it initialises RAM, which is too early for Rust code, so it is assembly.
+
+// Unlocks the watchdog, then disables it within the next 256 bus cycles
+#[cfg(not(feature = "mcu_kl26"))]
+core::arch::global_asm!(
+    ".section .text.__pre_init, \"ax\"",
+    ".global __pre_init",
+    ".type __pre_init, %function",
+    ".thumb_func",
+    "__pre_init:",
+    "ldr r0, =0x4005200E", // WDOG_UNLOCK
+    "ldr r1, =0xC520",
+    "strh r1, [r0]",
+    "ldr r1, =0xD928",
+    "strh r1, [r0]",
+    "ldr r0, =0x40052000", // WDOG_STCTRLH
+    "ldr r1, =0x01D2",
+    "strh r1, [r0]",
+    "bx lr",
+);
+
+// Disables the COP watchdog
+#[cfg(feature = "mcu_kl26")]
+core::arch::global_asm!(
+    ".section .text.__pre_init, \"ax\"",
+    ".global __pre_init",
+    ".type __pre_init, %function",
+    ".thumb_func",
+    "__pre_init:",
+    "ldr r0, =0x40048100", // SIM_COPC
+    "movs r1, #0",
+    "str r1, [r0]",
+    "bx lr",
+);
+
+/// A pin configured as GPIO output.
+pub struct Output {
+    port: u32,
+    bit: u32,
+}
+
+impl Output {
+    /// Clocks `port` (0 for port A) and makes pin `bit` of it an output.
+    /// Unsafe because nothing stops two `Output`s for the same pin.
+    pub unsafe fn new(port: u32, bit: u32) -> Output {
+        ptr::write_volatile(SIM_SCGC5, ptr::read_volatile(SIM_SCGC5) | 1 << (9 + port));
+        ptr::write_volatile((PORT_PCR + port * 0x1000 + bit * 4) as *mut u32, 1 << 8);
+        let pin = Output { port, bit };
+        ptr::write_volatile(pin.register(PDDR), ptr::read_volatile(pin.register(PDDR)) | 1 << bit);
+        pin
+    }
+
+    fn register(&self, offset: u32) -> *mut u32 {
+        (GPIO + self.port * 0x40 + offset) as *mut u32
+    }
+
+    /// Writes the pin's bit to one of the set, clear and toggle registers,
+    /// which leave the other pins of the port alone.
+    fn write(&mut self, offset: u32) {
+        unsafe { ptr::write_volatile(self.register(offset), 1 << self.bit) }
+    }
+}
+
+impl ErrorType for Output {
+    type Error = Infallible;
+}
+
+impl OutputPin for Output {
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        self.write(PCOR);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        self.write(PSOR);
+        Ok(())
+    }
+}
+
+impl StatefulOutputPin for Output {
+    fn is_set_high(&mut self) -> Result<bool, Infallible> {
+        Ok(unsafe { ptr::read_volatile(self.register(PDOR)) } & 1 << self.bit != 0)
+    }
+
+    fn is_set_low(&mut self) -> Result<bool, Infallible> {
+        self.is_set_high().map(|high| !high)
+    }
+
+    fn toggle(&mut self) -> Result<(), Infallible> {
+        self.write(PTOR);
+        Ok(())
+    }
+}
+"#;
+
+const CORTEX_M_RT_MEMORY: &'static str = r#"/* Memory layout of the {description} */
+MEMORY
+{
+  FLASH : ORIGIN = 0x00000000, LENGTH = {flash_size}
+  RAM : ORIGIN = {ram_start}, LENGTH = {ram_size}
+}
+
+/* The flash configuration field at 0x400 is kept free of code */
+SECTIONS
+{
+  .flashconfig 0x400 :
+  {
+    KEEP(*(.flashconfig));
+  } > FLASH
+} INSERT AFTER .vector_table;
+
+_stext = 0x410;
+"#;
+
+const CORTEX_M_RT_BUILD: &'static str = r#"//! Puts memory.x where the linker finds it.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+fn main() {
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    fs::copy("memory.x", out.join("memory.x")).unwrap();
+    println!("cargo:rustc-link-search={}", out.display());
+    println!("cargo:rerun-if-changed=memory.x");
+    println!("cargo:rerun-if-changed=build.rs");
+}
+"#;
+
+const CORTEX_M_RT_CARGOCONFIG: &'static str = r#"[build]
+target = "{target}"
+
+[target.{target}]
+rustflags = ["-C", "link-arg=-Tlink.x"]
+"#;
+
+const CORTEX_M_RT_MANIFEST: &'static str = r#"
+[features]
+default = ["{feature}"]
+{feature} = []
+
+[dependencies]
+cortex-m = "0.7"
+cortex-m-rt = "0.7"
+embedded-hal = "1.0"
+
+[profile.dev]
+panic = "abort"
+
+[profile.release]
+panic = "abort"
+debug = true
+lto = true
+"#;
+
+/// The names and descriptions of the built-in templates.
+pub const BUILTIN: &'static [(&'static str, &'static str)] = &[
+    ("blink", "Blinks the LED with zinc"),
+    ("usb-serial", "Echoes USB serial input with the teensy3 crate"),
+    ("minimal", "Turns on the LED without any framework"),
+    ("library", "A no_std library crate"),
+    ("cortex-m-rt", "Blinks the LED with cortex-m-rt on stable rust"),
+];
+
+pub fn builtin(name: &str) -> Option<Template> {
+    let (kind, files, manifest): (Kind, Vec<(&str, &str)>, &str) = match name {
+        "blink" => (Kind::Bin, vec![("src/main.rs", BLINK_MAIN)], ZINC_MANIFEST),
+        "usb-serial" => (Kind::Bin, vec![("src/main.rs", USB_SERIAL_MAIN)], TEENSY3_MANIFEST),
+        "minimal" => (Kind::Bin, vec![("src/main.rs", MINIMAL_MAIN), ("layout.ld", MINIMAL_LAYOUT)],
+                      MINIMAL_MANIFEST),
+        "library" => (Kind::Lib, vec![("src/lib.rs", LIBRARY_LIB)], LIBRARY_MANIFEST),
+        "cortex-m-rt" => {
+            (Kind::Bin,
+             vec![("src/main.rs", CORTEX_M_RT_MAIN),
+                  ("src/hal.rs", CORTEX_M_RT_HAL),
+                  ("memory.x", CORTEX_M_RT_MEMORY),
+                  ("build.rs", CORTEX_M_RT_BUILD),
+                  (".cargo/config.toml", CORTEX_M_RT_CARGOCONFIG)],
+             CORTEX_M_RT_MANIFEST)
+        }
+        _ => return None,
+    };
+    let toolchain = match name {
+        "cortex-m-rt" => Some("stable"),
+        "usb-serial" => Some(TEENSY3_TOOLCHAIN),
+        _ => None,
+    };
+    let boards: &[&str] = match name {
+        "blink" => ZINC_BOARDS,
+        "usb-serial" => TEENSY3_BOARDS,
+        _ => &[],
+    };
+    let description = BUILTIN.iter().find(|&&(n, _)| n == name).map_or("", |&(_, d)| d);
+    Some(Template {
+        name: name.into(),
+        description: description.into(),
+        kind: kind,
+        files: files.iter().map(|&(path, contents)| (path.into(), contents.into())).collect(),
+        manifest: manifest.into(),
+        // Only zinc's nightly needs the generated target specification.
+        target_spec: toolchain.is_none(),
+        toolchain: toolchain.map(|t| t.into()),
+        boards: boards.iter().map(|b| b.to_string()).collect(),
+    })
+}
+
+/// Whether the template can be used for `board`.
+pub fn supports(template: &Template, board: &str) -> bool {
+    template.boards.is_empty() || template.boards.iter().any(|b| b == board)
+}
+
+/// Whether `path` is a cargo configuration, which is merged instead of copied.
+pub fn is_cargo_config(path: &str) -> bool {
+    path == ".cargo/config" || path == ".cargo/config.toml"
+}
+
+/// The cargo configuration the template brings, if any.
+pub fn cargo_config(template: &Template) -> Option<&str> {
+    template.files.iter().find(|&&(ref path, _)| is_cargo_config(path))
+        .map(|&(_, ref contents)| &contents[..])
+}
+
+/// Where templates are looked up by name before the built-in ones.
+pub fn user_dir() -> Option<PathBuf> {
+    let config = if cfg!(target_os = "macos") {
+        env::home_dir().map(|home| home.join("Library").join("Application Support"))
+    } else if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        match env::var_os("XDG_CONFIG_HOME") {
+            Some(ref dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+            _ => env::home_dir().map(|home| home.join(".config")),
+        }
+    };
+    config.map(|dir| dir.join("cargo-teensy").join("templates"))
+}
+
+pub fn is_git_url(name: &str) -> bool {
+    name.starts_with("https://") || name.starts_with("http://") || name.starts_with("git@") ||
+    name.starts_with("ssh://") || name.starts_with("git://") || name.ends_with(".git")
+}
+
+/// Replaces the `{key}` placeholders in `text` with their values in a single
+/// pass, so values are not searched for placeholders again. `{{key}}` is
+/// written as `{key}`.
+pub fn substitute(text: &str, values: &[(&str, String)]) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let mut replaced = None;
+        for &(key, ref value) in values {
+            let placeholder = format!("{{{}}}", key);
+            if rest.starts_with(&format!("{{{}}}", placeholder)) {
+                replaced = Some((placeholder.clone(), placeholder.len() + 2));
+                break;
+            }
+            if rest.starts_with(&placeholder) {
+                replaced = Some((value.clone(), placeholder.len()));
+                break;
+            }
+        }
+        let (replacement, skip) = replaced.unwrap_or(("{".into(), 1));
+        out.push_str(&replacement);
+        rest = &rest[skip..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn read_to_string(path: &Path) -> Result<String, Error> {
+    let mut s = String::new();
+    try!(File::open(path).and_then(|mut f| f.read_to_string(&mut s))
+         .map_err(|ioerr| Error::io(&path.to_string_lossy(), ioerr)));
+    Ok(s)
+}
+
+/// Collects the files below `dir`, with paths relative to `root`.
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<(String, String)>)
+                 -> Result<(), Error> {
+    let name = dir.to_string_lossy().into_owned();
+    let mut entries = try!(fs::read_dir(dir).and_then(|entries| entries.collect::<Result<Vec<_>, _>>())
+                           .map_err(|ioerr| Error::io(&name, ioerr)));
+    entries.sort_by_key(|entry| entry.path());
+    for entry in entries {
+        let path = entry.path();
+        if path.is_dir() {
+            try!(collect_files(root, &path, files));
+        } else {
+            let relative = path.strip_prefix(root).unwrap_or(&path).to_string_lossy()
+                .replace("\\", "/");
+            files.push((relative, try!(read_to_string(&path))));
+        }
+    }
+    Ok(())
+}
+
+/// Loads a template from a directory, see the module documentation.
+pub fn from_dir(dir: &Path) -> Result<Template, Error> {
+    let dirname = dir.to_string_lossy().into_owned();
+    let mut template = Template {
+        name: dir.file_name().map_or(dirname.clone(), |n| n.to_string_lossy().into_owned()),
+        description: String::new(),
+        kind: Kind::Bin,
+        files: Vec::new(),
+        manifest: String::new(),
+        target_spec: true,
+        toolchain: None,
+        boards: Vec::new(),
+    };
+
+    let settings = dir.join("template.toml");
+    if settings.exists() {
+        let s = try!(read_to_string(&settings));
+        let invalid = |msg: &str| Error::Usage(format!("{}: {}", settings.to_string_lossy(), msg));
+        let table = try!(toml::Parser::new(&s).parse().ok_or(invalid("invalid TOML")));
+        if let Some(description) = table.get("description") {
+            template.description = try!(description.as_str()
+                                        .ok_or(invalid("description must be a string"))).into();
+        }
+        template.kind = match table.get("kind").map(|k| k.as_str()) {
+            None | Some(Some("bin")) => Kind::Bin,
+            Some(Some("lib")) => Kind::Lib,
+            _ => return Err(invalid("kind must be \"bin\" or \"lib\"")),
+        };
+        if let Some(target_spec) = table.get("target-spec") {
+            template.target_spec = try!(target_spec.as_bool()
+                                        .ok_or(invalid("target-spec must be true or false")));
+        }
+        if let Some(toolchain) = table.get("toolchain") {
+            template.toolchain = Some(try!(toolchain.as_str()
+                                           .ok_or(invalid("toolchain must be a string"))).into());
+        }
+        if let Some(boards) = table.get("boards") {
+            let boards = try!(boards.as_slice().ok_or(invalid("boards must be a list")));
+            for board in boards {
+                template.boards.push(try!(board.as_str()
+                                          .ok_or(invalid("boards must be a list of names"))).into());
+            }
+        }
+    }
+
+    let manifest = dir.join("manifest.toml");
+    if manifest.exists() {
+        template.manifest = try!(read_to_string(&manifest));
+    }
+
+    let files = dir.join("files");
+    if files.is_dir() {
+        try!(collect_files(&files, &files, &mut template.files));
+    }
+    if template.files.is_empty() && template.manifest.is_empty() {
+        return Err(Error::Usage(format!("{} is not a template: it has neither files/ nor \
+                                         manifest.toml", dirname)));
+    }
+    Ok(template)
+}
+
+/// Finds a template that is not a git URL: a directory, a template in the
+/// user template directory or a built-in template.
+pub fn find(name: &str) -> Result<Template, Error> {
+    let path = Path::new(name);
+    if name.contains('/') || name.contains('\\') || name.starts_with('.') {
+        return if path.is_dir() {
+            from_dir(path)
+        } else {
+            Err(Error::Usage(format!("Template directory {} not found", name)))
+        };
+    }
+    if let Some(dir) = user_dir().map(|dir| dir.join(name)) {
+        if dir.is_dir() {
+            return from_dir(&dir);
+        }
+    }
+    builtin(name).ok_or_else(|| {
+        let mut available = BUILTIN.iter().map(|&(n, d)| format!("  {:12} {}", n, d))
+            .collect::<Vec<_>>();
+        let user = user_dir().and_then(|dir| fs::read_dir(dir).ok())
+            .map(|entries| entries.filter_map(|e| e.ok())
+                 .filter(|e| e.path().is_dir())
+                 .map(|e| {
+                     let description = from_dir(&e.path()).map(|t| t.description)
+                         .unwrap_or_default();
+                     format!("  {:12} {}", e.file_name().to_string_lossy(), description)
+                 })
+                 .collect::<Vec<_>>())
+            .unwrap_or_default();
+        available.extend(user);
+        Error::Usage(format!("Unknown template '{}'. Available templates:\n{}",
+                             name, available.join("\n")))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::process;
+
+    /// A template directory with the given files, removed again on drop.
+    struct TemplateDir(PathBuf);
+
+    impl TemplateDir {
+        fn new(name: &str, files: &[(&str, &str)]) -> TemplateDir {
+            let dir = env::temp_dir().join(format!("cargo-teensy-{}-{}", process::id(), name));
+            let _ = fs::remove_dir_all(&dir);
+            for &(path, contents) in files {
+                let path = dir.join(path);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                File::create(&path).unwrap().write_all(contents.as_bytes()).unwrap();
+            }
+            TemplateDir(dir)
+        }
+    }
+
+    impl Drop for TemplateDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn invalid(settings: &str) -> String {
+        let dir = TemplateDir::new("invalid", &[("template.toml", settings),
+                                                ("files/src/main.rs", "")]);
+        match from_dir(&dir.0) {
+            Ok(_) => panic!("{} was accepted", settings),
+            Err(e) => e.message(),
+        }
+    }
+
+    #[test]
+    fn substitute_fills_placeholders() {
+        let values = [("name", "blinky".to_string()), ("board", "teensy31".to_string())];
+        assert_eq!(substitute("{name} on {board}", &values), "blinky on teensy31");
+    }
+
+    #[test]
+    fn substitute_does_not_fill_placeholders_in_values() {
+        let values = [("name", "{board}".to_string()), ("board", "teensy31".to_string())];
+        assert_eq!(substitute("{name}", &values), "{board}");
+    }
+
+    #[test]
+    fn substitute_keeps_escaped_placeholders_and_other_braces() {
+        let values = [("name", "blinky".to_string())];
+        assert_eq!(substitute("{{name}} is {name}", &values), "{name} is blinky");
+        assert_eq!(substitute("fn main() { println!(\"{{}} {}\", 1); }", &values),
+                   "fn main() { println!(\"{{}} {}\", 1); }");
+        assert_eq!(substitute("{{{name}}}", &values), "{{name}}");
+    }
+
+    #[test]
+    fn from_dir_reads_the_template_settings_and_files() {
+        let dir = TemplateDir::new("full", &[
+            ("template.toml", "description = \"A library\"\nkind = \"lib\"\n\
+                               target-spec = false\ntoolchain = \"stable\"\n\
+                               boards = [\"teensy31\", \"teensy32\"]\n"),
+            ("manifest.toml", "[dependencies]\ncortex-m = \"0.7\"\n"),
+            ("files/src/lib.rs", "//! {name}\n"),
+            ("files/.cargo/config.toml", "[build]\n"),
+        ]);
+        let template = from_dir(&dir.0).unwrap();
+        assert_eq!(template.description, "A library");
+        assert_eq!(template.kind, Kind::Lib);
+        assert!(!template.target_spec);
+        assert_eq!(template.toolchain, Some("stable".into()));
+        assert_eq!(template.boards, vec!["teensy31".to_string(), "teensy32".to_string()]);
+        assert_eq!(template.manifest, "[dependencies]\ncortex-m = \"0.7\"\n");
+        assert_eq!(template.files, vec![(".cargo/config.toml".to_string(), "[build]\n".to_string()),
+                                        ("src/lib.rs".to_string(), "//! {name}\n".to_string())]);
+    }
+
+    #[test]
+    fn from_dir_defaults_without_template_toml() {
+        let dir = TemplateDir::new("defaults", &[("files/src/main.rs", "fn main() {}\n")]);
+        let template = from_dir(&dir.0).unwrap();
+        assert_eq!(template.kind, Kind::Bin);
+        assert!(template.target_spec);
+        assert_eq!(template.toolchain, None);
+        assert!(template.boards.is_empty());
+    }
+
+    #[test]
+    fn from_dir_rejects_invalid_settings() {
+        assert!(invalid("kind = \"exe\"").ends_with("kind must be \"bin\" or \"lib\""));
+        assert!(invalid("description = 1").ends_with("description must be a string"));
+        assert!(invalid("target-spec = \"no\"").ends_with("target-spec must be true or false"));
+        assert!(invalid("toolchain = 1").ends_with("toolchain must be a string"));
+        assert!(invalid("boards = \"teensy31\"").ends_with("boards must be a list"));
+        assert!(invalid("boards = [1]").ends_with("boards must be a list of names"));
+        assert!(invalid("kind = ").ends_with("invalid TOML"));
+    }
+
+    #[test]
+    fn from_dir_needs_files_or_a_manifest() {
+        let dir = TemplateDir::new("empty", &[("template.toml", "description = \"Nothing\"\n")]);
+        assert!(from_dir(&dir.0).is_err());
+    }
+}
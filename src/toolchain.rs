@@ -0,0 +1,279 @@
+//! Rust toolchain names like `nightly-2016-05-24-x86_64-unknown-linux-gnu`,
+//! as used by rustup and in `.travis.yml`, and how they compare.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use regex::Regex;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Channel {
+    Stable,
+    Beta,
+    Nightly,
+    /// A stable release like `1.9.0`.
+    Version(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    pub year: u32,
+    pub month: u32,
+    pub day: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Toolchain {
+    pub channel: Channel,
+    pub date: Option<Date>,
+    pub host: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Same,
+    Older,
+    Newer,
+    DifferentChannel,
+    DifferentHost,
+}
+
+impl Date {
+    fn parse(s: &str) -> Option<Date> {
+        let parts = s.split('-').map(|p| p.parse().ok()).collect::<Option<Vec<u32>>>();
+        match parts {
+            Some(ref p) if p.len() == 3 && p[1] >= 1 && p[1] <= 12 && p[2] >= 1 && p[2] <= 31 => {
+                Some(Date { year: p[0], month: p[1], day: p[2] })
+            }
+            _ => None,
+        }
+    }
+
+    fn next_day(&self) -> Date {
+        let leap = self.year % 4 == 0 && (self.year % 100 != 0 || self.year % 400 == 0);
+        let days = match self.month {
+            2 if leap => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        };
+        if self.day < days {
+            Date { day: self.day + 1, ..*self }
+        } else if self.month < 12 {
+            Date { month: self.month + 1, day: 1, ..*self }
+        } else {
+            Date { year: self.year + 1, month: 1, day: 1 }
+        }
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+/// Compares dotted version numbers like `1.9.0` numerically.
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let numbers = |v: &str| v.split('.').map(|n| n.parse().unwrap_or(0)).collect::<Vec<u32>>();
+    numbers(a).cmp(&numbers(b))
+}
+
+impl Toolchain {
+    /// Parses a toolchain name: a channel, optionally followed by a date and a host triple.
+    pub fn parse(spec: &str) -> Result<Toolchain, String> {
+        let re = Regex::new(concat!(r"^(stable|beta|nightly|\d+\.\d+(?:\.\d+)?)",
+                                    r"(?:-(\d{4}-\d{2}-\d{2}))?(?:-(.+))?$")).unwrap();
+        let caps = try!(re.captures(spec.trim())
+            .ok_or(format!("'{}' is not a toolchain name like nightly-2016-05-24", spec)));
+        let channel = match caps.at(1).unwrap_or("") {
+            "stable" => Channel::Stable,
+            "beta" => Channel::Beta,
+            "nightly" => Channel::Nightly,
+            version => Channel::Version(version.into()),
+        };
+        let date = match caps.at(2) {
+            Some(date) => Some(try!(Date::parse(date).ok_or(format!("'{}' has an invalid date",
+                                                                    spec)))),
+            None => None,
+        };
+        // Host triples start with the architecture, a date like 2016-5-24
+        // that is not written as YYYY-MM-DD would end up here.
+        if caps.at(3).map_or(false, |host| host.starts_with(|c: char| c.is_digit(10))) {
+            return Err(format!("'{}' has an invalid date, write it like 2016-05-24", spec));
+        }
+        Ok(Toolchain { channel: channel, date: date, host: caps.at(3).map(|h| h.into()) })
+    }
+
+    /// Reads the toolchain from the output of `rustc -vV`. rustc only knows
+    /// the date of its last commit, which is usually the day before the
+    /// nightly is named after.
+    pub fn from_rustc_verbose_version(output: &str) -> Result<Toolchain, String> {
+        let field = |name: &str| {
+            output.lines().find(|l| l.starts_with(name)).map(|l| l[name.len()..].trim())
+        };
+        let release = try!(field("release:").ok_or("rustc -vV: no release".to_string()));
+        let channel = if release.ends_with("-nightly") || release.ends_with("-dev") {
+            Channel::Nightly
+        } else if release.contains("-beta") {
+            Channel::Beta
+        } else {
+            Channel::Version(release.into())
+        };
+        let date = match channel {
+            Channel::Nightly => field("commit-date:").and_then(Date::parse).map(|d| d.next_day()),
+            _ => None,
+        };
+        Ok(Toolchain { channel: channel, date: date, host: field("host:").map(|h| h.into()) })
+    }
+
+    /// `stable`, `beta` or `nightly`.
+    pub fn channel_name(&self) -> &'static str {
+        match self.channel {
+            Channel::Stable | Channel::Version(_) => "stable",
+            Channel::Beta => "beta",
+            Channel::Nightly => "nightly",
+        }
+    }
+
+    /// Whether the name tells exactly which release it is.
+    pub fn is_exact(&self) -> bool {
+        match self.channel {
+            Channel::Version(_) => true,
+            _ => self.date.is_some(),
+        }
+    }
+
+    /// How this installed toolchain relates to the `required` one. Parts
+    /// that `required` leaves open, like the date or the host, match anything.
+    pub fn compare(&self, required: &Toolchain) -> Comparison {
+        match (&self.host, &required.host) {
+            (&Some(ref installed), &Some(ref required)) if installed != required => {
+                return Comparison::DifferentHost;
+            }
+            _ => {}
+        }
+        let ordering = match (&self.channel, &required.channel) {
+            (&Channel::Version(ref installed), &Channel::Version(ref required)) => {
+                compare_versions(installed, required)
+            }
+            (&Channel::Version(_), &Channel::Stable) |
+            (&Channel::Stable, &Channel::Stable) |
+            (&Channel::Beta, &Channel::Beta) |
+            (&Channel::Nightly, &Channel::Nightly) => {
+                match (self.date, required.date) {
+                    (Some(installed), Some(required)) => installed.cmp(&required),
+                    _ => Ordering::Equal,
+                }
+            }
+            _ => return Comparison::DifferentChannel,
+        };
+        match ordering {
+            Ordering::Less => Comparison::Older,
+            Ordering::Equal => Comparison::Same,
+            Ordering::Greater => Comparison::Newer,
+        }
+    }
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Channel::Stable => write!(f, "stable"),
+            Channel::Beta => write!(f, "beta"),
+            Channel::Nightly => write!(f, "nightly"),
+            Channel::Version(ref version) => write!(f, "{}", version),
+        }
+    }
+}
+
+/// The toolchain name without the host, as given to `rustup override set`.
+impl fmt::Display for Toolchain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{}", self.channel));
+        if let Some(date) = self.date {
+            try!(write!(f, "-{}", date));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(spec: &str) -> Toolchain {
+        Toolchain::parse(spec).unwrap()
+    }
+
+    #[test]
+    fn parse_reads_channel_date_and_host() {
+        assert_eq!(parse("nightly-2016-05-24-x86_64-unknown-linux-gnu"), Toolchain {
+            channel: Channel::Nightly,
+            date: Some(Date { year: 2016, month: 5, day: 24 }),
+            host: Some("x86_64-unknown-linux-gnu".into()),
+        });
+        assert_eq!(parse("1.9.0"), Toolchain {
+            channel: Channel::Version("1.9.0".into()),
+            date: None,
+            host: None,
+        });
+    }
+
+    #[test]
+    fn parse_rejects_dates_not_written_as_yyyy_mm_dd() {
+        assert!(Toolchain::parse("nightly-2016-5-24").is_err());
+        assert!(Toolchain::parse("nightly-2016-13-01").is_err());
+    }
+
+    #[test]
+    fn compare_orders_by_date_within_a_channel() {
+        let required = parse("nightly-2016-05-24");
+        assert_eq!(parse("nightly-2016-05-24").compare(&required), Comparison::Same);
+        assert_eq!(parse("nightly-2016-05-23").compare(&required), Comparison::Older);
+        assert_eq!(parse("nightly-2016-06-01").compare(&required), Comparison::Newer);
+        assert_eq!(parse("beta-2016-05-24").compare(&required), Comparison::DifferentChannel);
+        assert_eq!(parse("nightly").compare(&required), Comparison::Same);
+    }
+
+    #[test]
+    fn compare_matches_versions_against_stable() {
+        assert_eq!(parse("1.9.0").compare(&parse("stable")), Comparison::Same);
+        assert_eq!(parse("1.9.0").compare(&parse("1.10.0")), Comparison::Older);
+        assert_eq!(parse("1.9.0").compare(&parse("nightly")), Comparison::DifferentChannel);
+    }
+
+    #[test]
+    fn compare_checks_the_host_only_if_both_name_one() {
+        let installed = parse("nightly-2016-05-24-x86_64-unknown-linux-gnu");
+        assert_eq!(installed.compare(&parse("nightly-2016-05-24")), Comparison::Same);
+        assert_eq!(installed.compare(&parse("nightly-2016-05-24-i686-unknown-linux-gnu")),
+                   Comparison::DifferentHost);
+    }
+
+    #[test]
+    fn from_rustc_verbose_version_names_nightlies_after_the_day_after_the_commit() {
+        let output = "rustc 1.10.0-nightly (cd6a40017 2016-05-23)\n\
+                      binary: rustc\n\
+                      commit-hash: cd6a400175ed3a5b8d0d2d8fd1a4c0ad1b6c3d4c\n\
+                      commit-date: 2016-05-23\n\
+                      host: x86_64-unknown-linux-gnu\n\
+                      release: 1.10.0-nightly\n";
+        let toolchain = Toolchain::from_rustc_verbose_version(output).unwrap();
+        assert_eq!(toolchain, parse("nightly-2016-05-24-x86_64-unknown-linux-gnu"));
+
+        let stable = Toolchain::from_rustc_verbose_version("release: 1.9.0\n").unwrap();
+        assert_eq!(stable, parse("1.9.0"));
+    }
+
+    #[test]
+    fn next_day_rolls_over_months_and_years() {
+        let date = |y, m, d| Date { year: y, month: m, day: d };
+        assert_eq!(date(2016, 5, 23).next_day(), date(2016, 5, 24));
+        assert_eq!(date(2016, 2, 28).next_day(), date(2016, 2, 29));
+        assert_eq!(date(2015, 2, 28).next_day(), date(2015, 3, 1));
+        assert_eq!(date(1900, 2, 28).next_day(), date(1900, 3, 1));
+        assert_eq!(date(2016, 4, 30).next_day(), date(2016, 5, 1));
+        assert_eq!(date(2016, 12, 31).next_day(), date(2017, 1, 1));
+    }
+}
@@ -0,0 +1,84 @@
+//! Where to get the external programs cargo-teensy relies on.
+
+use std::fs::File;
+use std::io::Read;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Distro {
+    Fedora,
+    Debian,
+    Arch,
+    MacOs,
+    Other,
+}
+
+/// Tells Linux distributions apart by `ID` and `ID_LIKE` of /etc/os-release.
+fn distro_from_os_release(os_release: &str) -> Distro {
+    let mut ids = Vec::new();
+    for line in os_release.lines() {
+        if line.starts_with("ID=") || line.starts_with("ID_LIKE=") {
+            let value = line.splitn(2, '=').nth(1).unwrap_or("").trim_matches('"');
+            ids.extend(value.split_whitespace().map(|id| id.to_string()));
+        }
+    }
+    for id in &ids {
+        match &id[..] {
+            "fedora" | "rhel" | "centos" => return Distro::Fedora,
+            "debian" | "ubuntu" => return Distro::Debian,
+            "arch" | "manjaro" => return Distro::Arch,
+            _ => {}
+        }
+    }
+    Distro::Other
+}
+
+pub fn distro() -> Distro {
+    if cfg!(target_os = "macos") {
+        return Distro::MacOs;
+    }
+    let mut s = String::new();
+    match File::open("/etc/os-release").and_then(|mut f| f.read_to_string(&mut s)) {
+        Ok(_) => distro_from_os_release(&s),
+        Err(_) => Distro::Other,
+    }
+}
+
+/// The command that installs `tool` on `distro`, or where to download it.
+fn install_hint_for(tool: &str, distro: Distro) -> String {
+    match (tool, distro) {
+        ("cargo", _) | ("rustup", _) | ("rustc", _) => {
+            "Install rustup from https://rustup.rs, it brings cargo and rustc along.".into()
+        }
+        ("arm-none-eabi-gcc", Distro::Fedora) | ("arm-none-eabi-ar", Distro::Fedora) => {
+            "Install with: sudo dnf install arm-none-eabi-gcc-cs arm-none-eabi-binutils-cs \
+             arm-none-eabi-newlib".into()
+        }
+        ("arm-none-eabi-gcc", Distro::Debian) | ("arm-none-eabi-ar", Distro::Debian) => {
+            "Install with: sudo apt-get install gcc-arm-none-eabi binutils-arm-none-eabi \
+             libnewlib-arm-none-eabi".into()
+        }
+        ("arm-none-eabi-gcc", Distro::Arch) | ("arm-none-eabi-ar", Distro::Arch) => {
+            "Install with: sudo pacman -S arm-none-eabi-gcc arm-none-eabi-binutils \
+             arm-none-eabi-newlib".into()
+        }
+        ("arm-none-eabi-gcc", Distro::MacOs) | ("arm-none-eabi-ar", Distro::MacOs) => {
+            "Install with: brew install --cask gcc-arm-embedded".into()
+        }
+        ("arm-none-eabi-gcc", _) | ("arm-none-eabi-ar", _) => {
+            "Download the GNU Arm Embedded Toolchain from \
+             https://developer.arm.com/open-source/gnu-toolchain/gnu-rm and add its bin \
+             directory to $PATH.".into()
+        }
+        ("openssl", Distro::Fedora) => "Install with: sudo dnf install openssl-devel".into(),
+        ("openssl", Distro::Debian) => "Install with: sudo apt-get install libssl-dev".into(),
+        ("openssl", Distro::Arch) => "Install with: sudo pacman -S openssl".into(),
+        ("openssl", _) => {
+            "Install the OpenSSL development headers, or point OPENSSL_DIR at them.".into()
+        }
+        (_, _) => format!("Install `{}` and make sure it can be found in $PATH.", tool),
+    }
+}
+
+pub fn install_hint(tool: &str) -> String {
+    install_hint_for(tool, distro())
+}
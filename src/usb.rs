@@ -0,0 +1,112 @@
+//! HalfKay `Transport` over libusb.
+//!
+//! Only used on Linux. macOS and Windows keep the HID interface of the
+//! bootloader to themselves, so claiming it fails there; `hid` is used instead.
+
+use std::time::Duration;
+
+use rusb::{self, DeviceHandle, GlobalContext};
+
+use halfkay::{Transport, VENDOR_ID, HALFKAY_PRODUCT_ID, SERIAL_PRODUCT_ID, REBOOTOR_PRODUCT_ID};
+
+const HID_SET_REPORT: u8 = 0x09;
+const CDC_SET_LINE_CODING: u8 = 0x20;
+const REQUEST_TYPE_CLASS_INTERFACE_OUT: u8 = 0x21;
+/// 134 baud tells the Teensy USB serial code to jump into the bootloader.
+const REBOOT_LINE_CODING: [u8; 7] = [0x86, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08];
+const WRITE_TIMEOUT_MS: u64 = 500;
+
+pub struct UsbTransport {
+    handle: Option<DeviceHandle<GlobalContext>>,
+}
+
+impl UsbTransport {
+    pub fn new() -> UsbTransport {
+        UsbTransport { handle: None }
+    }
+}
+
+fn open_device(product_id: u16) -> Result<Option<DeviceHandle<GlobalContext>>, String> {
+    let handle = match rusb::open_device_with_vid_pid(VENDOR_ID, product_id) {
+        Some(handle) => handle,
+        None => return Ok(None),
+    };
+    // Not supported everywhere; claiming fails below if a kernel driver is still bound.
+    let _ = handle.set_auto_detach_kernel_driver(true);
+    try!(handle.claim_interface(0)
+        .map_err(|e| format!("Unable to claim USB interface of {:04x}:{:04x}: {}",
+                             VENDOR_ID, product_id, e)));
+    Ok(Some(handle))
+}
+
+fn set_report(handle: &DeviceHandle<GlobalContext>, data: &[u8]) -> Result<(), String> {
+    handle.write_control(REQUEST_TYPE_CLASS_INTERFACE_OUT, HID_SET_REPORT, 0x0200, 0, data,
+                         Duration::from_millis(WRITE_TIMEOUT_MS))
+        .map(|_| ())
+        .map_err(|e| format!("{}", e))
+}
+
+impl Transport for UsbTransport {
+    fn open(&mut self) -> Result<bool, String> {
+        if self.handle.is_none() {
+            self.handle = try!(open_device(HALFKAY_PRODUCT_ID));
+        }
+        Ok(self.handle.is_some())
+    }
+
+    fn write(&mut self, report: &[u8]) -> Result<(), String> {
+        match self.handle {
+            Some(ref handle) => set_report(handle, report),
+            None => Err("Device is not open".into()),
+        }
+    }
+
+    fn close(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.release_interface(0);
+        }
+    }
+
+    fn soft_reboot(&mut self) -> Result<bool, String> {
+        let handle = match try!(open_device(SERIAL_PRODUCT_ID)) {
+            Some(handle) => handle,
+            None => return Ok(false),
+        };
+        try!(handle.write_control(REQUEST_TYPE_CLASS_INTERFACE_OUT, CDC_SET_LINE_CODING, 0, 0,
+                                  &REBOOT_LINE_CODING, Duration::from_millis(10000))
+            .map_err(|e| format!("Soft reboot failed: {}", e)));
+        Ok(true)
+    }
+
+    fn hard_reboot(&mut self) -> Result<bool, String> {
+        let handle = match try!(open_device(REBOOTOR_PRODUCT_ID)) {
+            Some(handle) => handle,
+            None => return Ok(false),
+        };
+        // The rebootor resets the Teensy and may vanish before it answers.
+        let _ = set_report(&handle, b"reboot");
+        Ok(true)
+    }
+}
+
+/// Tries to open the first connected Teensy. Returns its product id, or
+/// `None` if no Teensy is connected.
+pub fn check_access() -> Result<Option<u16>, String> {
+    let devices = try!(rusb::devices().map_err(|e| format!("Cannot list USB devices: {}", e)));
+    for device in devices.iter() {
+        let descriptor = match device.device_descriptor() {
+            Ok(descriptor) => descriptor,
+            Err(_) => continue,
+        };
+        let product_id = descriptor.product_id();
+        if descriptor.vendor_id() != VENDOR_ID ||
+           ![HALFKAY_PRODUCT_ID, SERIAL_PRODUCT_ID, REBOOTOR_PRODUCT_ID].contains(&product_id) {
+            continue;
+        }
+        return match device.open() {
+            Ok(_) => Ok(Some(product_id)),
+            Err(e) => Err(format!("Cannot open {:04x}:{:04x}: {}", VENDOR_ID, product_id, e)),
+        };
+    }
+    Ok(None)
+}